@@ -34,11 +34,12 @@ async fn main() {
     } else {
         HyperHttpOrHttpsAcceptor::new_http(listener)
     };
+    // Don't let a single client with a broken TLS setup take down the server
+    let acceptor = acceptor.with_error_policy(ErrorPolicy::SkipTransient);
 
-    let mut server = Server::builder(acceptor).serve(make_svc);
+    let server = Server::builder(acceptor).serve(make_svc);
 
-    loop {
-        let res = (&mut server).await;
-        eprintln!("Error: {:?}", res);
+    if let Err(err) = server.await {
+        eprintln!("Error: {:?}", err);
     }
 }
@@ -0,0 +1,43 @@
+use flexible_hyper_server_tls::*;
+use http_body_util::Full;
+use hyper1::body::{Bytes, Incoming};
+use hyper1::service::service_fn;
+use hyper1::{Request, Response};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+const CERT_DATA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/certs/cert.pem"));
+const KEY_DATA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/certs/key.pem"));
+
+async fn hello_world(_req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    Ok(Response::new(Full::new(Bytes::from("Hello, World"))))
+}
+
+#[tokio::main]
+async fn main() {
+    let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
+
+    let tls_acceptor =
+        tlsconfig::get_tlsacceptor_from_pem_data(CERT_DATA, KEY_DATA, tlsconfig::HttpProtocol::Both)
+            .unwrap();
+    let acceptor =
+        HyperHttpOrHttpsAcceptor::new_http_and_https(listener, tls_acceptor, Duration::from_secs(10))
+            .with_error_policy(ErrorPolicy::SkipTransient);
+
+    // Stop accepting on Ctrl+C, then wait for open connections to finish
+    let shutdown = acceptor.shutdown_handle();
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        shutdown.shutdown_gracefully(Duration::from_secs(5));
+    });
+
+    let server = acceptor.serve(|conn: &HttpOrHttpsConnection| {
+        println!("Remote address: {}", conn.remote_addr());
+        service_fn(hello_world)
+    });
+
+    if let Err(err) = server.await {
+        eprintln!("Error: {:?}", err);
+    }
+}
@@ -1,39 +1,150 @@
 use futures_util::future::BoxFuture;
 use futures_util::stream::FuturesUnordered;
-use futures_util::{FutureExt, StreamExt};
+use futures_util::{FutureExt, Stream, StreamExt};
 use hyper::server::accept::Accept;
+use std::ops::ControlFlow;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 
+use crate::cidr::Cidr;
 use crate::conn::{ConnKind, HttpOrHttpsConnection};
+use crate::event::{
+    AcceptFailure, AcceptorEvent, EventHandler, HandshakeFailure, HandshakeFailureKind,
+};
+use crate::filter::{IpFilter, IpFilterHandle};
+use crate::limit::{ConnectionLimits, ConnectionPermit, LimitAction};
+use crate::listener::{AnyListener, AnyStream, Listener, PeerAddr};
+use crate::metrics::{AcceptorMetrics, ListenerMode, SharedMetrics};
+use crate::mode::{AcceptorKind, ModeHandle, TlsHandle};
+use crate::proxy::{ProxyHeaderError, ProxyProtocol};
+use crate::queue::{HandshakeQueueHandle, QueueSlot};
+use crate::rewind::Rewind;
+use crate::shutdown::ShutdownHandle;
+use crate::socket::TcpSocketOptions;
 
-/// Choose to accept either a HTTP or HTTPS connection
-pub struct HyperHttpOrHttpsAcceptor {
-    listener: tokio::net::TcpListener,
-    kind: AcceptorKind,
+/// Choose to accept either a HTTP or HTTPS connection, or both on the same port
+///
+/// An acceptor can accept from several listeners at once, each with its own choice of HTTP or HTTPS.
+/// The first listener is given to the constructor, and more can be added with the `with_*` methods.
+///
+/// Listeners can be anything that implements [`Listener`], as long as they're all the same type. Use
+/// [`AnyListener`](crate::AnyListener) to mix TCP and Unix domain socket listeners.
+pub struct HyperHttpOrHttpsAcceptor<L: Listener = tokio::net::TcpListener> {
+    listeners: Vec<ListenerState<L>>,
+    // Index of the listener to poll first, so that a busy listener can't starve the ones after it
+    next_listener: usize,
+    // Handshakes from every listener share a queue
+    encryption_futures: FuturesUnordered<EncryptionFuture<L::Io>>,
+    handshake_queue: HandshakeQueueHandle,
+    max_handshakes: Option<usize>,
+    spawn_handshakes: bool,
+    // The most connections to accept in a row before letting other tasks run, and how many are left until then
+    accept_budget: usize,
+    budget_left: usize,
+    limits: Option<ConnectionLimits>,
+    event_handler: Option<EventHandler>,
+    error_policy: ErrorPolicy,
+    // The initial and maximum delay
+    accept_backoff: Option<(std::time::Duration, std::time::Duration)>,
+    proxy_protocol: Option<ProxyProtocol>,
+    first_byte_timeout: Option<std::time::Duration>,
+    stream_setup: Option<StreamSetup<L::Io>>,
+    ip_filter: IpFilterHandle,
+    metrics: Option<SharedMetrics>,
+    shutdown: ShutdownHandle,
+    // Set once shutdown has started, firing when handshakes in progress are out of time
+    shutdown_deadline: Option<Pin<Box<tokio::time::Sleep>>>,
 }
 
-enum AcceptorKind {
-    Http,
-    Https {
-        tls_acceptor: tokio_rustls::TlsAcceptor,
-        timeout: std::time::Duration,
-        // Future has to be boxed because Rust doesn't allow writing out the full type
-        // Side benefit of allow us to use Timeout without needing pin projection
-        encryption_futures: FuturesUnordered<
-            tokio::time::Timeout<BoxFuture<'static, Result<HttpOrHttpsConnection, AcceptorError>>>,
-        >,
-    },
+// Future has to be boxed because Rust doesn't allow writing out the full type
+// Handshakes come with the mode of their listener, and successful ones with how long they took, for the metrics
+type EncryptionFuture<IO> = BoxFuture<'static, (ListenerMode, HandshakeResult<IO>)>;
+
+type HandshakeResult<IO> =
+    Result<(HttpOrHttpsConnection<IO>, std::time::Duration), HandshakeFailure>;
+
+const DEFAULT_ACCEPT_BUDGET: usize = 64;
+
+type StreamSetup<IO> = Box<dyn Fn(&IO) -> std::io::Result<()> + Send + Sync>;
+
+struct ListenerState<L> {
+    inner: L,
+    kind: ModeHandle,
+    backoff: Backoff,
 }
 
-impl HyperHttpOrHttpsAcceptor {
-    /// Create an acceptor that will accept HTTP connections
-    pub const fn new_http(listener: tokio::net::TcpListener) -> Self {
+#[derive(Default)]
+struct Backoff {
+    // The delay after the last failed accept, doubled for each failure in a row
+    delay: Option<std::time::Duration>,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<L> ListenerState<L> {
+    fn new(inner: L, kind: AcceptorKind) -> Self {
         Self {
-            listener,
-            kind: AcceptorKind::Http,
+            inner,
+            kind: ModeHandle::new(kind),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+impl Backoff {
+    // Check whether the listener is done waiting after its last failure
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(sleep) = &mut self.sleep {
+            futures_util::ready!(sleep.poll_unpin(cx));
+            self.sleep = None;
         }
+        Poll::Ready(())
+    }
+
+    // Start waiting after a failure, returning how long for
+    fn start(
+        &mut self,
+        initial_delay: std::time::Duration,
+        max_delay: std::time::Duration,
+    ) -> std::time::Duration {
+        let delay = self
+            .delay
+            .map_or(initial_delay, |delay| (delay * 2).min(max_delay));
+        self.delay = Some(delay);
+        self.sleep = Some(Box::pin(tokio::time::sleep(delay)));
+        delay
+    }
+}
+
+impl<L: Listener> HyperHttpOrHttpsAcceptor<L> {
+    fn new(listener: L, kind: AcceptorKind) -> Self {
+        Self {
+            listeners: vec![ListenerState::new(listener, kind)],
+            next_listener: 0,
+            encryption_futures: FuturesUnordered::new(),
+            handshake_queue: HandshakeQueueHandle::default(),
+            max_handshakes: None,
+            spawn_handshakes: false,
+            accept_budget: DEFAULT_ACCEPT_BUDGET,
+            budget_left: DEFAULT_ACCEPT_BUDGET,
+            limits: None,
+            event_handler: None,
+            error_policy: ErrorPolicy::YieldAll,
+            accept_backoff: None,
+            proxy_protocol: None,
+            first_byte_timeout: None,
+            stream_setup: None,
+            ip_filter: IpFilterHandle::default(),
+            metrics: None,
+            shutdown: ShutdownHandle::new(),
+            shutdown_deadline: None,
+        }
+    }
+
+    /// Create an acceptor that will accept HTTP connections
+    pub fn new_http(listener: L) -> Self {
+        Self::new(listener, AcceptorKind::Http)
     }
 
     /// Create an acceptor that will accept HTTPS connections using the provided `TlsAcceptor`
@@ -41,19 +152,585 @@ impl HyperHttpOrHttpsAcceptor {
     /// `handshake_timeout` is the length of time that should be allowed to finish a TLS handshake before we drop the connection.
     /// Setting it to 0 will not disable the timeout, but will instead instantly drop every connection (you probably don't want this).
     pub fn new_https(
-        listener: tokio::net::TcpListener,
+        listener: L,
         tls_acceptor: tokio_rustls::TlsAcceptor,
         handshake_timeout: std::time::Duration,
     ) -> Self {
-        Self {
+        Self::new(
             listener,
-            kind: AcceptorKind::Https {
+            AcceptorKind::Https {
                 tls_acceptor,
                 timeout: handshake_timeout,
-                encryption_futures: FuturesUnordered::new(),
+                allow_http: false,
             },
+        )
+    }
+
+    /// Create an acceptor that will accept both HTTP and HTTPS connections on the same listener
+    ///
+    /// The first byte sent by each client is read to decide whether it's starting a TLS handshake. If it is,
+    /// the connection is encrypted using the provided `TlsAcceptor`, otherwise it is passed along as plain HTTP.
+    /// Either way the byte is given back, so nothing sent by the client is lost.
+    ///
+    /// `handshake_timeout` covers both waiting for the first byte and finishing the TLS handshake, see [`Self::new_https`].
+    pub fn new_http_and_https(
+        listener: L,
+        tls_acceptor: tokio_rustls::TlsAcceptor,
+        handshake_timeout: std::time::Duration,
+    ) -> Self {
+        Self::new(
+            listener,
+            AcceptorKind::Https {
+                tls_acceptor,
+                timeout: handshake_timeout,
+                allow_http: true,
+            },
+        )
+    }
+
+    fn with_listener(mut self, listener: L, kind: AcceptorKind) -> Self {
+        self.listeners.push(ListenerState::new(listener, kind));
+        self
+    }
+
+    /// Also accept HTTP connections from another listener
+    ///
+    /// See [`Self::new_http`]. If the acceptor uses [`AnyListener`](crate::AnyListener), this can be given a
+    /// `TcpListener` or `UnixListener` directly.
+    #[must_use]
+    pub fn with_http(self, listener: impl Into<L>) -> Self {
+        self.with_listener(listener.into(), AcceptorKind::Http)
+    }
+
+    /// Also accept HTTPS connections from another listener
+    ///
+    /// See [`Self::new_https`]
+    #[must_use]
+    pub fn with_https(
+        self,
+        listener: impl Into<L>,
+        tls_acceptor: tokio_rustls::TlsAcceptor,
+        handshake_timeout: std::time::Duration,
+    ) -> Self {
+        self.with_listener(
+            listener.into(),
+            AcceptorKind::Https {
+                tls_acceptor,
+                timeout: handshake_timeout,
+                allow_http: false,
+            },
+        )
+    }
+
+    /// Also accept both HTTP and HTTPS connections from another listener
+    ///
+    /// See [`Self::new_http_and_https`]
+    #[must_use]
+    pub fn with_http_and_https(
+        self,
+        listener: impl Into<L>,
+        tls_acceptor: tokio_rustls::TlsAcceptor,
+        handshake_timeout: std::time::Duration,
+    ) -> Self {
+        self.with_listener(
+            listener.into(),
+            AcceptorKind::Https {
+                tls_acceptor,
+                timeout: handshake_timeout,
+                allow_http: true,
+            },
+        )
+    }
+
+    /// Limit the number of TLS handshakes that can be in progress at once
+    ///
+    /// When the limit is reached, HTTPS listeners stop being polled until a handshake finishes or times out, so new
+    /// connections wait in the OS backlog. Connections from HTTP listeners that are waiting for their first byte (see
+    /// [`Self::with_first_byte_timeout`]) or PROXY protocol header are limited to the same number, but counted
+    /// separately, so idle HTTP clients can't stop HTTPS connections from being accepted or the other way around.
+    /// HTTP listeners aren't limited at all if neither of those is set, since their connections never wait.
+    /// Setting it to 0 will stop HTTPS connections from ever being accepted (you probably don't want this).
+    #[must_use]
+    pub const fn with_max_handshakes(mut self, max_handshakes: usize) -> Self {
+        self.max_handshakes = Some(max_handshakes);
+        self
+    }
+
+    /// Run each handshake on its own task, so that handshakes can run in parallel on a multi-threaded runtime
+    ///
+    /// By default, handshakes are run by whichever task is polling the acceptor, which means all of the TLS work is
+    /// done on one thread at a time. Handshakes still time out the same way, and are still cancelled when the
+    /// acceptor is dropped or runs out of time to shut down. Use [`Self::with_max_handshakes`] to limit how many run
+    /// at once.
+    #[must_use]
+    pub const fn with_spawned_handshakes(mut self) -> Self {
+        self.spawn_handshakes = true;
+        self
+    }
+
+    /// Limit the number of connections accepted in a row before the acceptor lets other tasks run (64 by default)
+    ///
+    /// Once the budget is used up, the acceptor returns pending once (waking itself up straight away), so that other
+    /// tasks on the same thread get to run, including ones serving connections that were already accepted. This
+    /// stops a flood of new connections from holding up everything else, even when there's always another one
+    /// waiting. Finished handshakes are yielded before new connections are accepted, so they can't be held up either.
+    /// At least one connection is always accepted, so setting it to 0 is the same as 1.
+    #[must_use]
+    pub const fn with_accept_budget(mut self, accept_budget: usize) -> Self {
+        let accept_budget = if accept_budget == 0 { 1 } else { accept_budget };
+        self.accept_budget = accept_budget;
+        self.budget_left = accept_budget;
+        self
+    }
+
+    /// Limit the number of connections that can be open at once
+    ///
+    /// Connections count towards the limit from the moment they're accepted (including during the TLS handshake)
+    /// until the `HttpOrHttpsConnection` is dropped. `on_limit` decides what happens to new connections once the limit
+    /// is reached.
+    #[must_use]
+    pub fn with_max_connections(mut self, max_connections: usize, on_limit: LimitAction) -> Self {
+        let limits = self.limits.get_or_insert_with(ConnectionLimits::default);
+        limits.max_connections = Some(max_connections);
+        limits.on_limit = on_limit;
+        self
+    }
+
+    /// Limit the number of connections that can be open at once from a single IP address
+    ///
+    /// The address of a connection is only known once it has been accepted, so connections over this limit are
+    /// always closed straight away.
+    #[must_use]
+    pub fn with_max_connections_per_ip(mut self, max_connections: usize) -> Self {
+        self.limits
+            .get_or_insert_with(ConnectionLimits::default)
+            .max_per_ip = Some(max_connections);
+        self
+    }
+
+    /// Set a function to be called with everything that happens while accepting connections
+    ///
+    /// This includes failed TLS handshakes, which are otherwise easy to miss since timeouts aren't yielded as errors.
+    /// The function is called from within the accept loop, so it should return quickly.
+    #[must_use]
+    pub fn with_event_handler(
+        mut self,
+        handler: impl Fn(AcceptorEvent<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Choose which errors are yielded by the acceptor
+    ///
+    /// Yielding an error makes hyper's `Server` resolve with it, so skipping transient errors keeps the server running
+    /// when a single client misbehaves. Skipped errors are still reported to the event handler.
+    #[must_use]
+    pub const fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Wait before accepting again when a listener fails with an error that isn't transient
+    ///
+    /// These errors usually mean the process has run out of resources (like file descriptors), so trying again
+    /// straight away would fail the same way. Instead of yielding the error, the listener waits for `initial_delay`,
+    /// doubling the delay for every failure in a row up to `max_delay`. Failures are still reported to the event handler.
+    #[must_use]
+    pub const fn with_accept_backoff(
+        mut self,
+        initial_delay: std::time::Duration,
+        max_delay: std::time::Duration,
+    ) -> Self {
+        self.accept_backoff = Some((initial_delay, max_delay));
+        self
+    }
+
+    /// Expect connections from load balancers in `trusted_sources` to start with a PROXY protocol header
+    ///
+    /// Both version 1 and 2 of the protocol are accepted. The header is read before the TLS handshake (or before
+    /// passing the connection along as plain HTTP) and must arrive within `timeout`, otherwise the connection is
+    /// dropped. The client address it contains is available from `HttpOrHttpsConnection::client_addr`.
+    ///
+    /// Connections from other addresses are accepted as normal, without reading a header. Connections that didn't come
+    /// over IP (like over a Unix socket) are always expected to send one, since they can only come from the same machine.
+    /// Connection limits per IP address still apply to the address of the load balancer.
+    #[must_use]
+    pub fn with_proxy_protocol(
+        mut self,
+        timeout: std::time::Duration,
+        trusted_sources: impl IntoIterator<Item = Cidr>,
+    ) -> Self {
+        self.proxy_protocol = Some(ProxyProtocol {
+            timeout,
+            trusted_sources: trusted_sources.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Drop HTTP connections if the client doesn't send anything within `timeout`
+    ///
+    /// Without this, connections from HTTP listeners are handed to hyper straight away, so clients that connect and
+    /// never send anything tie up a connection until hyper times them out (if it does at all). With it, they wait in
+    /// the handshake queue for their first byte, like HTTPS connections wait for their handshake. Timeouts are
+    /// reported to the event handler as failed handshakes, and how many can wait at once is limited by
+    /// [`Self::with_max_handshakes`]. HTTPS listeners are already covered by the handshake timeout.
+    #[must_use]
+    pub const fn with_first_byte_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.first_byte_timeout = Some(timeout);
+        self
+    }
+
+    /// Set a function to be called with every stream as soon as it's accepted, before anything is read from it
+    ///
+    /// Useful for setting socket options on listeners other than `TcpListener`, which can use
+    /// [`Self::with_tcp_socket_options`] instead. If the function fails, the connection is closed and the error is
+    /// reported to the event handler.
+    #[must_use]
+    pub fn with_stream_setup(
+        mut self,
+        setup: impl Fn(&L::Io) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.stream_setup = Some(Box::new(setup));
+        self
+    }
+
+    /// Only accept connections from IP addresses allowed by `filter`
+    ///
+    /// Connections from other addresses are closed as soon as they're accepted, before anything is read from them.
+    /// Clients that didn't connect over IP (like over a Unix socket) are always allowed. When using the PROXY protocol,
+    /// this checks the address of the load balancer, not the client behind it.
+    ///
+    /// The rules can be changed later with [`Self::ip_filter_handle`].
+    #[must_use]
+    pub fn with_ip_filter(self, filter: IpFilter) -> Self {
+        self.ip_filter.set(filter);
+        self
+    }
+
+    /// Report what the acceptor is doing to `metrics`, see [`AcceptorMetrics`]
+    ///
+    /// Open connections are counted even without [`Self::with_max_connections`].
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: impl AcceptorMetrics + 'static) -> Self {
+        let metrics = SharedMetrics(std::sync::Arc::new(metrics));
+        self.limits
+            .get_or_insert_with(ConnectionLimits::default)
+            .set_metrics(metrics.clone());
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Get a handle that can be used to switch the listener at `listener_index` between HTTP and HTTPS once the
+    /// acceptor has been given to hyper
+    ///
+    /// Listeners are numbered in the order they were added, starting at 0 for the one given to the constructor.
+    /// Returns `None` if there's no listener at that index.
+    pub fn mode_handle(&self, listener_index: usize) -> Option<ModeHandle> {
+        self.listeners
+            .get(listener_index)
+            .map(|listener| listener.kind.clone())
+    }
+
+    /// Get a handle that can be used to replace the `TlsAcceptor` of the listener at `listener_index` once the
+    /// acceptor has been given to hyper, such as to load a renewed certificate
+    ///
+    /// See [`TlsHandle`](crate::TlsHandle). Returns `None` if there's no listener at that index.
+    pub fn tls_handle(&self, listener_index: usize) -> Option<TlsHandle> {
+        self.listeners
+            .get(listener_index)
+            .map(|listener| listener.kind.tls_handle())
+    }
+
+    /// Get a handle that can be used to change the IP filter once the acceptor has been given to hyper
+    ///
+    /// See [`Self::with_ip_filter`]. Without a filter, every address is allowed.
+    pub fn ip_filter_handle(&self) -> IpFilterHandle {
+        self.ip_filter.clone()
+    }
+
+    /// Get a handle that can be used to shut down the acceptor once it's been given to hyper
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get the number of TLS handshakes currently in progress
+    ///
+    /// Use [`Self::handshake_queue_handle`] to check once the acceptor has been given to hyper.
+    pub fn pending_handshakes(&self) -> usize {
+        self.handshake_queue.pending_handshakes()
+    }
+
+    /// Get a handle that can be used to see how many TLS handshakes are in progress once the acceptor has been given
+    /// to hyper
+    pub fn handshake_queue_handle(&self) -> HandshakeQueueHandle {
+        self.handshake_queue.clone()
+    }
+
+    /// Accept the next connection, for use without hyper 0.14's `Server`
+    ///
+    /// The acceptor also implements `Stream`, which yields the same connections.
+    /// Returns `None` once the acceptor has been shut down with its [`ShutdownHandle`] and every handshake in
+    /// progress has finished. Errors are returned (or skipped) the same way as when the acceptor is given to hyper,
+    /// see [`Self::with_error_policy`].
+    pub async fn accept(&mut self) -> Option<Result<HttpOrHttpsConnection<L::Io>, AcceptorError>> {
+        std::future::poll_fn(|cx| self.poll_next_conn(cx)).await
+    }
+}
+
+impl HyperHttpOrHttpsAcceptor<tokio::net::TcpListener> {
+    /// Set options on every TCP stream as soon as it's accepted, see [`TcpSocketOptions`]
+    ///
+    /// Replaces any function set with [`Self::with_stream_setup`]
+    #[must_use]
+    pub fn with_tcp_socket_options(self, options: TcpSocketOptions) -> Self {
+        self.with_stream_setup(move |stream| options.apply(stream))
+    }
+}
+
+impl HyperHttpOrHttpsAcceptor<AnyListener> {
+    /// Set options on every TCP stream as soon as it's accepted, see [`TcpSocketOptions`]
+    ///
+    /// Streams from Unix domain sockets are left alone. Replaces any function set with [`Self::with_stream_setup`].
+    #[must_use]
+    pub fn with_tcp_socket_options(self, options: TcpSocketOptions) -> Self {
+        self.with_stream_setup(move |stream| match stream {
+            AnyStream::Tcp(tcp) => options.apply(tcp),
+            #[cfg(unix)]
+            AnyStream::Unix(_) => Ok(()),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl HyperHttpOrHttpsAcceptor<AnyListener> {
+    /// Create an acceptor from the sockets passed in by systemd socket activation (`LISTEN_FDS`)
+    ///
+    /// The name of each socket (set with `FileDescriptorName=` in the socket unit) decides how it's used:
+    /// `http` accepts HTTP, `https` accepts HTTPS and `http-and-https` accepts both, like the matching constructors.
+    /// HTTPS connections are encrypted using `tls_acceptor`. Listeners are numbered in the order systemd passed them in.
+    ///
+    /// This lets the service listen on privileged ports like 443 without running as root. The sockets can only be
+    /// taken once, so calling this again fails.
+    ///
+    /// # Errors
+    /// Errors if no sockets were passed in, if any of them isn't a listening stream socket or has an unknown name,
+    /// or if a socket is for HTTPS but `tls_acceptor` is `None`.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn from_systemd(
+        tls_acceptor: Option<&tokio_rustls::TlsAcceptor>,
+        handshake_timeout: std::time::Duration,
+    ) -> Result<Self, crate::SystemdError> {
+        use crate::SystemdError;
+
+        let mut acceptor: Option<Self> = None;
+        for (name, listener) in crate::systemd::take_listeners()? {
+            let kind = match name.as_str() {
+                "http" => AcceptorKind::Http,
+                "https" | "http-and-https" => AcceptorKind::Https {
+                    tls_acceptor: tls_acceptor
+                        .cloned()
+                        .ok_or_else(|| SystemdError::MissingTlsAcceptor(name.clone()))?,
+                    timeout: handshake_timeout,
+                    allow_http: name == "http-and-https",
+                },
+                _ => return Err(SystemdError::UnknownName(name)),
+            };
+            acceptor = Some(match acceptor {
+                Some(acceptor) => acceptor.with_listener(listener, kind),
+                None => Self::new(listener, kind),
+            });
+        }
+        acceptor.ok_or(SystemdError::NoSockets)
+    }
+}
+
+// Not a method so that it can be used while a listener is borrowed
+fn report(event_handler: Option<&EventHandler>, event: AcceptorEvent<'_>) {
+    #[cfg(feature = "tracing")]
+    if let AcceptorEvent::AcceptFailed(failure) = event {
+        crate::trace::accept_failed(failure);
+    }
+    if let Some(handler) = event_handler {
+        handler(event);
+    }
+}
+
+// The content type of a TLS handshake record, which every ClientHello is sent in
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+// Read the first byte sent by the client, which is given back when the stream is read from
+// A client that closes the connection without sending anything is left for hyper to deal with
+async fn read_first_byte<IO: AsyncRead + Unpin>(mut stream: IO) -> std::io::Result<Rewind<IO>> {
+    let mut first_byte = [0; 1];
+    if stream.read(&mut first_byte).await? == 0 {
+        Ok(Rewind::new(stream))
+    } else {
+        Ok(Rewind::with_prefix(stream, first_byte[0]))
+    }
+}
+
+// Turn the result of a part of the handshake that has a timeout into the reason it failed
+fn with_timeout<T>(
+    res: Result<std::io::Result<T>, tokio::time::error::Elapsed>,
+) -> Result<T, HandshakeFailureKind> {
+    match res {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(err)) => Err(err.into()),
+        Err(_) => Err(HandshakeFailureKind::Timeout),
+    }
+}
+
+// Everything known about a connection as soon as it's accepted, before anything is read from it
+struct Accepted {
+    peer_addr: PeerAddr,
+    listener_index: usize,
+    permit: Option<ConnectionPermit>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Accepted {
+    fn into_conn<IO>(
+        self,
+        kind: ConnKind<IO>,
+        proxy_header: Option<crate::ProxyHeader>,
+    ) -> HttpOrHttpsConnection<IO> {
+        HttpOrHttpsConnection {
+            peer_addr: self.peer_addr,
+            listener_index: self.listener_index,
+            kind,
+            proxy_header,
+            _permit: self.permit,
+            #[cfg(feature = "tracing")]
+            span: self.span,
+        }
+    }
+}
+
+// Read the PROXY protocol header if one is expected, then wait for the first byte if it's HTTP or encrypt the
+// connection if it's HTTPS
+fn handshake<IO: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    mut stream: IO,
+    accepted: Accepted,
+    slot: QueueSlot,
+    kind: AcceptorKind,
+    proxy_timeout: Option<std::time::Duration>,
+    first_byte_timeout: Option<std::time::Duration>,
+) -> EncryptionFuture<IO> {
+    let started = std::time::Instant::now();
+    let mode = kind.mode();
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::handshake_span(&accepted.span);
+
+    let handshake = async move {
+        // Counted as in progress until it's done or dropped
+        let _slot = slot;
+        // Pass along the details of the connection if the handshake failed
+        let failed = |kind| {
+            let failure = HandshakeFailure {
+                peer_addr: accepted.peer_addr.clone(),
+                listener_index: accepted.listener_index,
+                elapsed: started.elapsed(),
+                kind,
+            };
+            #[cfg(feature = "tracing")]
+            crate::trace::handshake_failed(&failure);
+            failure
+        };
+
+        let proxy_header = match proxy_timeout {
+            Some(timeout) => Some(
+                tokio::time::timeout(timeout, crate::proxy::read_header(&mut stream))
+                    .await
+                    .unwrap_or(Err(ProxyHeaderError::Timeout))
+                    .map_err(|err| failed(HandshakeFailureKind::ProxyHeader(err)))?,
+            ),
+            None => None,
+        };
+
+        let kind = match kind {
+            AcceptorKind::Http => match first_byte_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, read_first_byte(stream)).await
+                {
+                    Ok(Ok(stream)) => Ok(ConnKind::Http(stream)),
+                    Ok(Err(err)) => Err(HandshakeFailureKind::FirstByte(err)),
+                    Err(_) => Err(HandshakeFailureKind::Timeout),
+                },
+                None => Ok(ConnKind::Http(Rewind::new(stream))),
+            },
+            AcceptorKind::Https {
+                tls_acceptor,
+                timeout,
+                allow_http,
+            } => {
+                let handshake = async {
+                    let stream = if allow_http {
+                        let stream = read_first_byte(stream).await?;
+                        // Anything that doesn't look like a ClientHello is passed along as plain HTTP
+                        if stream.prefix() != Some(TLS_HANDSHAKE_RECORD) {
+                            return Ok(ConnKind::Http(stream));
+                        }
+                        stream
+                    } else {
+                        Rewind::new(stream)
+                    };
+                    let conn = tls_acceptor.accept(stream).await?;
+                    #[cfg(feature = "tracing")]
+                    crate::trace::record_tls(conn.get_ref().1);
+                    Ok(ConnKind::Https(Box::new(conn)))
+                };
+                with_timeout(tokio::time::timeout(timeout, handshake).await)
+            }
+        }
+        .map_err(failed)?;
+
+        Ok((accepted.into_conn(kind, proxy_header), started.elapsed()))
+    };
+    #[cfg(feature = "tracing")]
+    let handshake = tracing::Instrument::instrument(handshake, span);
+    handshake.map(move |res| (mode, res)).boxed()
+}
+
+// Run the handshake on its own task, giving back a future that finishes along with it
+// The task is aborted if the future is dropped, so that clearing the queue cancels it just like any other handshake
+fn spawn_handshake<IO: Send + 'static>(
+    handshake: EncryptionFuture<IO>,
+    peer_addr: PeerAddr,
+    listener_index: usize,
+    mode: ListenerMode,
+) -> EncryptionFuture<IO> {
+    let started = std::time::Instant::now();
+    let mut task = AbortOnDrop(tokio::spawn(handshake));
+    async move {
+        match (&mut task.0).await {
+            Ok(output) => output,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            // Cancelled by something other than us, like the runtime shutting down
+            Err(err) => {
+                let failure = HandshakeFailure {
+                    peer_addr,
+                    listener_index,
+                    elapsed: started.elapsed(),
+                    kind: HandshakeFailureKind::Io(std::io::Error::other(err)),
+                };
+                #[cfg(feature = "tracing")]
+                crate::trace::handshake_failed(&failure);
+                (mode, Err(failure))
+            }
         }
     }
+    .boxed()
+}
+
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 /// Error when accepting connections
@@ -62,74 +739,368 @@ pub enum AcceptorError {
     /// Failed to connect to client over TCP
     #[error("TCP connection to client failed")]
     TcpConnect(#[source] std::io::Error),
-    /// Failed to make TLS handshake with client
+    /// Failed to make TLS handshake with client, or to read the first byte sent by an HTTP client
     #[error("TLS handshake with client failed")]
     TlsHandshake(#[source] std::io::Error),
+    /// Failed to read the PROXY protocol header sent by a load balancer
+    #[error("PROXY protocol header from client was invalid")]
+    ProxyHeader(#[source] ProxyHeaderError),
+}
+
+impl AcceptorError {
+    /// Check whether the error only affects a single client, rather than the listener as a whole
+    ///
+    /// Failed TLS handshakes, invalid PROXY protocol headers and TCP connections that were reset or aborted before they could be accepted are
+    /// transient. Other errors when accepting TCP connections (like running out of file descriptors) are not.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::TcpConnect(err) => is_connection_error(err),
+            Self::TlsHandshake(_) | Self::ProxyHeader(_) => true,
+        }
+    }
+}
+
+// Errors from accepting a TCP connection that only affect that connection
+fn is_connection_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Which errors the acceptor yields, see `HyperHttpOrHttpsAcceptor::with_error_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Yield every error (the default)
+    YieldAll,
+    /// Skip errors that only affect a single client, as decided by [`AcceptorError::is_transient`]
+    SkipTransient,
+}
+
+impl ErrorPolicy {
+    fn should_yield(self, err: &AcceptorError) -> bool {
+        match self {
+            Self::YieldAll => true,
+            Self::SkipTransient => !err.is_transient(),
+        }
+    }
 }
 
-impl Accept for HyperHttpOrHttpsAcceptor {
-    type Conn = HttpOrHttpsConnection;
+impl<L: Listener + Unpin> Accept for HyperHttpOrHttpsAcceptor<L> {
+    type Conn = HttpOrHttpsConnection<L::Io>;
     type Error = AcceptorError;
 
     fn poll_accept(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
-        // Necessary to allow partial borrows
-        let this = self.get_mut();
-
-        match &mut this.kind {
-            // If just a normal HTTP connection, just poll to accept the new TCP connection
-            AcceptorKind::Http => match this.listener.poll_accept(cx) {
-                Poll::Ready(Ok(stream)) => Poll::Ready(Some(Ok(HttpOrHttpsConnection {
-                    remote_addr: stream.1,
-                    kind: ConnKind::Http(stream.0),
-                }))),
-                Poll::Ready(Err(err)) => Poll::Ready(Some(Err(AcceptorError::TcpConnect(err)))),
-                Poll::Pending => Poll::Pending,
-            },
-            // Otherwise, if it's an HTTPS connection, check if we're ready to encrypt the connection
-            AcceptorKind::Https {
-                tls_acceptor,
-                timeout,
-                encryption_futures,
-            } => {
-                // Accept all pending TCP connections at once (this future won't be woken up for TCP unless we get a pending here)
-                loop {
-                    match this.listener.poll_accept(cx) {
-                        Poll::Ready(Ok(stream)) => {
-                            let tls_future = tls_acceptor
-                                .accept(stream.0)
-                                .map(move |f| {
-                                    // Map so that we can pass along the remote address
-                                    f.map(|conn| HttpOrHttpsConnection {
-                                        remote_addr: stream.1,
-                                        kind: ConnKind::Https(conn),
-                                    })
-                                    .map_err(AcceptorError::TlsHandshake)
-                                })
-                                .boxed();
-                            let timed_tls_future = tokio::time::timeout(*timeout, tls_future);
-                            encryption_futures.push(timed_tls_future);
+        self.get_mut().poll_next_conn(cx)
+    }
+}
+
+/// Yields connections the same way as when the acceptor is given to hyper, ending once it's shut down
+impl<L: Listener + Unpin> Stream for HyperHttpOrHttpsAcceptor<L> {
+    type Item = Result<HttpOrHttpsConnection<L::Io>, AcceptorError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_conn(cx)
+    }
+}
+
+impl<L: Listener> HyperHttpOrHttpsAcceptor<L> {
+    fn poll_next_conn(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<HttpOrHttpsConnection<L::Io>, AcceptorError>>> {
+        if self.shutdown_deadline.is_none() {
+            if let Some(grace_period) = self.shutdown.poll_requested(cx) {
+                // Dropping the listeners closes them, so new clients are refused rather than left in the backlog
+                self.listeners.clear();
+                self.shutdown_deadline = Some(Box::pin(tokio::time::sleep(grace_period)));
+            }
+        }
+        if let Some(deadline) = &mut self.shutdown_deadline {
+            if deadline.poll_unpin(cx).is_ready() && !self.encryption_futures.is_empty() {
+                self.encryption_futures.clear();
+                self.report_queue_len();
+            }
+            if self.encryption_futures.is_empty() {
+                return Poll::Ready(None);
+            }
+        }
+
+        // Finished handshakes go first, so that they can't be held up by a flood of new connections
+        if let Poll::Ready(res) = self.poll_handshakes(cx, false) {
+            return Poll::Ready(res);
+        }
+        let poll = if self.budget_left == 0 {
+            // Accepted too many connections in a row, so let other tasks run before accepting more
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            match self.poll_listeners(cx) {
+                ControlFlow::Break(res) => Poll::Ready(Some(res)),
+                // Polled again to start the handshakes that were just queued
+                ControlFlow::Continue(handshakes_full) => self.poll_handshakes(cx, handshakes_full),
+            }
+        };
+        // Other tasks get to run whenever we return pending, so the budget can start again
+        if poll.is_pending() {
+            self.budget_left = self.accept_budget;
+        }
+        poll
+    }
+
+    // Accept new connections from all of the listeners, breaking if one is ready to be yielded
+    // Continues with whether any listener was skipped because the handshake queue is full
+    fn poll_listeners(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> ControlFlow<Result<HttpOrHttpsConnection<L::Io>, AcceptorError>, bool> {
+        let mut handshakes_full = false;
+
+        let listener_count = self.listeners.len();
+        'listeners: for offset in 0..listener_count {
+            let listener_index = (self.next_listener + offset) % listener_count;
+            // Accept pending TCP connections until we get a pending (this future won't be woken up for TCP unless we do)
+            // or run out of budget
+            loop {
+                let ListenerState {
+                    inner,
+                    kind,
+                    backoff,
+                } = &mut self.listeners[listener_index];
+                // Leave connections in the backlog if there's no room to encrypt them (or wait for them to send something)
+                // HTTP connections are only queued if they wait for their first byte or might send a PROXY header
+                let queued = if kind.mode() != ListenerMode::Http {
+                    Some(self.handshake_queue.pending_handshakes())
+                } else if self.first_byte_timeout.is_some() || self.proxy_protocol.is_some() {
+                    Some(self.handshake_queue.pending_http_waits())
+                } else {
+                    None
+                };
+                if queued
+                    .zip(self.max_handshakes)
+                    .is_some_and(|(queued, max)| queued >= max)
+                {
+                    handshakes_full = true;
+                    break;
+                }
+                // Leave connections in the backlog if there's no room for them at all
+                if let Some(limits) = &self.limits {
+                    if limits.poll_ready(cx).is_pending() {
+                        break 'listeners;
+                    }
+                }
+                // Wait for the backoff to finish before trying again
+                if backoff.poll_ready(cx).is_pending() {
+                    break;
+                }
+                let accepted = inner.poll_accept(cx);
+                if accepted.is_ready() {
+                    self.budget_left -= 1;
+                }
+                match accepted {
+                    Poll::Ready(Ok((stream, peer_addr))) => {
+                        backoff.delay = None;
+                        // The mode can be changed at any time, so it's only checked once per connection
+                        let kind = kind.get();
+                        if let Some(metrics) = &self.metrics {
+                            metrics.connection_accepted(listener_index, kind.mode());
                         }
-                        Poll::Ready(Err(err)) => {
-                            return Poll::Ready(Some(Err(AcceptorError::TcpConnect(err))))
+                        if let Some(conn) =
+                            self.start_connection(stream, peer_addr, listener_index, kind)
+                        {
+                            self.next_listener = (listener_index + 1) % listener_count;
+                            return ControlFlow::Break(Ok(conn));
+                        }
+                    }
+                    Poll::Ready(Err(error)) => {
+                        let retry_in = match self.accept_backoff {
+                            Some((initial_delay, max_delay)) if !is_connection_error(&error) => {
+                                Some(backoff.start(initial_delay, max_delay))
+                            }
+                            _ => None,
+                        };
+                        let failure = AcceptFailure {
+                            listener_index,
+                            error,
+                            retry_in,
+                        };
+                        report(
+                            self.event_handler.as_ref(),
+                            AcceptorEvent::AcceptFailed(&failure),
+                        );
+                        let err = AcceptorError::TcpConnect(failure.error);
+                        if retry_in.is_none() && self.error_policy.should_yield(&err) {
+                            self.next_listener = (listener_index + 1) % listener_count;
+                            return ControlFlow::Break(Err(err));
                         }
-                        // Break on pending here so we can check on the TLS queue
-                        Poll::Pending => break,
                     }
+                    // Break on pending here so we can check on the other listeners and the TLS queue
+                    Poll::Pending => break,
+                }
+                if self.budget_left == 0 {
+                    // Nothing will wake us up for the connections that are still waiting, so do it ourselves, and
+                    // start with the next listener so that this one can't take the whole budget every time
+                    self.next_listener = (listener_index + 1) % listener_count;
+                    cx.waker().wake_by_ref();
+                    break 'listeners;
+                }
+            }
+        }
+
+        ControlFlow::Continue(handshakes_full)
+    }
+
+    // Set up a connection that was just accepted, returning it if it's ready to be yielded straight away
+    // Connections that are closed for being over the limits or that need a handshake aren't returned
+    fn start_connection(
+        &self,
+        stream: L::Io,
+        peer_addr: PeerAddr,
+        listener_index: usize,
+        kind: AcceptorKind,
+    ) -> Option<HttpOrHttpsConnection<L::Io>> {
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::connection_span(&peer_addr, listener_index, kind.mode());
+
+        // Close connections from addresses that aren't allowed by dropping them
+        if !self.ip_filter.allows(&peer_addr) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(parent: &span, "closing connection from address denied by the IP filter");
+            return None;
+        }
+        let permit = match &self.limits {
+            // Over the limit, so close the connection by dropping it
+            Some(limits) => Some(
+                limits
+                    .try_acquire(peer_addr.tcp().map(|addr| addr.ip()))
+                    .or_else(|| {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(parent: &span, "closing connection over the connection limits");
+                        None
+                    })?,
+            ),
+            None => None,
+        };
+        if let Some(setup) = &self.stream_setup {
+            if let Err(error) = setup(&stream) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(parent: &span, %error, "closing connection that failed to be set up");
+                // Not a problem with the listener, so it's only reported
+                let failure = AcceptFailure {
+                    listener_index,
+                    error,
+                    retry_in: None,
+                };
+                report(
+                    self.event_handler.as_ref(),
+                    AcceptorEvent::AcceptFailed(&failure),
+                );
+                return None;
+            }
+        }
+        let proxy_timeout = self
+            .proxy_protocol
+            .as_ref()
+            .and_then(|proxy| proxy.timeout_for(&peer_addr));
+        let accepted = Accepted {
+            peer_addr: peer_addr.clone(),
+            listener_index,
+            permit,
+            #[cfg(feature = "tracing")]
+            span,
+        };
+        // If just a normal HTTP connection, there's nothing more to do
+        if matches!(kind, AcceptorKind::Http)
+            && proxy_timeout.is_none()
+            && self.first_byte_timeout.is_none()
+        {
+            return Some(accepted.into_conn(ConnKind::Http(Rewind::new(stream)), None));
+        }
+        // Otherwise, queue it up to read the PROXY header, wait for the first byte or be encrypted
+        let mode = kind.mode();
+        let slot = if mode == ListenerMode::Http {
+            self.handshake_queue.start_http_wait()
+        } else {
+            self.handshake_queue.start_handshake()
+        };
+        let handshake = handshake(
+            stream,
+            accepted,
+            slot,
+            kind,
+            proxy_timeout,
+            self.first_byte_timeout,
+        );
+        self.encryption_futures.push(if self.spawn_handshakes {
+            spawn_handshake(handshake, peer_addr, listener_index, mode)
+        } else {
+            handshake
+        });
+        if let Some(metrics) = &self.metrics {
+            metrics.handshake_started(mode);
+        }
+        self.report_queue_len();
+        None
+    }
+
+    fn report_queue_len(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.handshake_queue_len(
+                self.handshake_queue.pending_handshakes(),
+                self.handshake_queue.pending_http_waits(),
+            );
+        }
+    }
+
+    // Check queue to see if any handshakes are done/timeouts hit
+    fn poll_handshakes(
+        &mut self,
+        cx: &mut Context<'_>,
+        handshakes_full: bool,
+    ) -> Poll<Option<Result<HttpOrHttpsConnection<L::Io>, AcceptorError>>> {
+        loop {
+            match self.encryption_futures.poll_next_unpin(cx) {
+                Poll::Ready(Some((mode, Ok((conn, elapsed))))) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.handshake_succeeded(mode, elapsed);
+                    }
+                    self.report_queue_len();
+                    return Poll::Ready(Some(Ok(conn)));
                 }
-                // Check queue to see if any handshakes are done/timeouts hit
-                loop {
-                    match encryption_futures.poll_next_unpin(cx) {
-                        // Already `map`ed to a Result<HttpOrHttpsConnection>, so no need to differentiate
-                        // between Some(Err) and Some(Ok)
-                        Poll::Ready(Some(Ok(res))) => return Poll::Ready(Some(res)),
-                        // An error here means that the timeout ran out, so just skip to the next one in the queue
-                        Poll::Ready(Some(Err(_))) => continue,
-                        _ => return Poll::Pending,
+                Poll::Ready(Some((mode, Err(failure)))) => {
+                    if let Some(metrics) = &self.metrics {
+                        if failure.is_timeout() {
+                            metrics.handshake_timed_out(mode, failure.elapsed);
+                        } else {
+                            metrics.handshake_failed(mode, failure.elapsed);
+                        }
+                    }
+                    self.report_queue_len();
+                    report(
+                        self.event_handler.as_ref(),
+                        AcceptorEvent::HandshakeFailed(&failure),
+                    );
+                    if let Some(err) = failure.into_error() {
+                        if self.error_policy.should_yield(&err) {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+                    // Otherwise the timeout ran out or the error is skipped, so just move on to the next one in the queue
+                    // The listeners that were skipped weren't polled, so we have to wake ourselves up to accept
+                    // into the space that was just freed
+                    if handshakes_full {
+                        cx.waker().wake_by_ref();
                     }
                 }
+                // The last handshake finished while shutting down
+                Poll::Ready(None) if self.shutdown_deadline.is_some() => return Poll::Ready(None),
+                _ => return Poll::Pending,
             }
         }
     }
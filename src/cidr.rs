@@ -0,0 +1,189 @@
+use std::net::IpAddr;
+use thiserror::Error;
+
+/// A block of IP addresses, like `10.0.0.0/8` or `2001:db8::/32`
+///
+/// Parse one from a string with `str::parse`, a single address without a prefix length is parsed as a block
+/// containing only that address. IPv4 blocks also contain the IPv4-mapped IPv6 versions of their addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// Error when creating a [`Cidr`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CidrError {
+    /// The address part of the block isn't a valid IP address
+    #[error("invalid IP address")]
+    InvalidAddr,
+    /// The prefix length isn't a number, or is longer than the address
+    #[error("invalid prefix length")]
+    InvalidPrefixLen,
+}
+
+impl Cidr {
+    /// Create a block from its first address and the number of bits that are fixed
+    ///
+    /// Bits of `addr` past the prefix length are ignored.
+    ///
+    /// # Errors
+    /// Errors if `prefix_len` is longer than the address (32 bits for IPv4 and 128 bits for IPv6).
+    pub const fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, CidrError> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(CidrError::InvalidPrefixLen);
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Get the first address of the block, as given when it was created
+    #[must_use]
+    pub const fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Get the number of bits that are fixed for every address in the block
+    #[must_use]
+    pub const fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Check whether `ip` is in the block
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(block) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(block), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(block) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Self { addr, prefix_len }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = addr.parse().map_err(|_| CidrError::InvalidAddr)?;
+        match prefix_len {
+            Some(prefix_len) => {
+                let prefix_len = prefix_len
+                    .parse()
+                    .map_err(|_| CidrError::InvalidPrefixLen)?;
+                Self::new(addr, prefix_len)
+            }
+            None => Ok(addr.into()),
+        }
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8).unwrap());
+        assert_eq!(cidr("10.1.2.3"), Cidr::new(ip("10.1.2.3"), 32).unwrap());
+        assert_eq!(cidr("2001:db8::/32").prefix_len(), 32);
+        assert_eq!(cidr("::1").prefix_len(), 128);
+        assert_eq!(cidr("0.0.0.0/0").to_string(), "0.0.0.0/0");
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<Cidr>(),
+            Err(CidrError::InvalidPrefixLen)
+        );
+        assert_eq!("::/129".parse::<Cidr>(), Err(CidrError::InvalidPrefixLen));
+        assert_eq!(
+            "10.0.0.0/".parse::<Cidr>(),
+            Err(CidrError::InvalidPrefixLen)
+        );
+        assert_eq!(
+            "10.0.0.0/-1".parse::<Cidr>(),
+            Err(CidrError::InvalidPrefixLen)
+        );
+        assert_eq!("10.0.0/8".parse::<Cidr>(), Err(CidrError::InvalidAddr));
+        assert_eq!("example.com".parse::<Cidr>(), Err(CidrError::InvalidAddr));
+        assert_eq!("".parse::<Cidr>(), Err(CidrError::InvalidAddr));
+    }
+
+    #[test]
+    fn contains_everything() {
+        assert!(cidr("0.0.0.0/0").contains(ip("0.0.0.0")));
+        assert!(cidr("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(ip("::")));
+        assert!(cidr("::/0").contains(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!cidr("::/0").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn contains_single_address() {
+        assert!(cidr("10.1.2.3/32").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.1.2.3/32").contains(ip("10.1.2.4")));
+        assert!(!cidr("10.1.2.3/32").contains(ip("10.1.2.2")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn contains_block() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        // Bits past the prefix length are ignored
+        assert!(cidr("10.1.2.3/8").contains(ip("10.4.5.6")));
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn contains_ipv4_mapped() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+        assert!(cidr("192.0.2.1/32").contains(ip("::ffff:192.0.2.1")));
+        // Only mapped addresses count, not ones that happen to end in the same bits
+        assert!(!cidr("10.0.0.0/8").contains(ip("::10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("64:ff9b::10.1.2.3")));
+    }
+}
@@ -2,49 +2,127 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use crate::limit::ConnectionPermit;
+use crate::listener::PeerAddr;
+use crate::proxy::ProxyHeader;
+use crate::rewind::Rewind;
+
 /// The stream connecting to a client over HTTP or HTTPS
 ///
-/// Yielded by `HyperHttpOrHttpsAcceptor`
+/// Yielded by `HyperHttpOrHttpsAcceptor`, over the stream type `IO` of its listeners
 #[derive(Debug)]
-pub struct HttpOrHttpsConnection {
-    pub(crate) remote_addr: std::net::SocketAddr,
-    pub(crate) kind: ConnKind,
+pub struct HttpOrHttpsConnection<IO = tokio::net::TcpStream> {
+    pub(crate) peer_addr: PeerAddr,
+    pub(crate) listener_index: usize,
+    pub(crate) kind: ConnKind<IO>,
+    pub(crate) proxy_header: Option<ProxyHeader>,
+    // Held for as long as the connection is open, so that it counts towards the connection limits
+    pub(crate) _permit: Option<ConnectionPermit>,
+    #[cfg(feature = "tracing")]
+    pub(crate) span: tracing::Span,
 }
 
 #[derive(Debug)]
-pub enum ConnKind {
-    Http(tokio::net::TcpStream),
-    Https(tokio_rustls::server::TlsStream<tokio::net::TcpStream>),
+pub enum ConnKind<IO> {
+    // Streams are wrapped so that the byte read to tell HTTP and HTTPS apart can be given back
+    Http(Rewind<IO>),
+    Https(Box<tokio_rustls::server::TlsStream<Rewind<IO>>>),
 }
 
-impl HttpOrHttpsConnection {
+impl<IO> HttpOrHttpsConnection<IO> {
     /// Get the remote `SocketAddr` of the connected client
-    pub const fn remote_addr(&self) -> std::net::SocketAddr {
-        self.remote_addr
+    ///
+    /// Clients that didn't connect over TCP don't have one, so this returns the unspecified address `0.0.0.0:0` for
+    /// them. Use [`Self::peer_addr`] to get their address instead.
+    ///
+    /// This is always the address the connection came from, which is the load balancer when using the PROXY protocol.
+    /// Use [`Self::client_addr`] to get the address of the client behind it.
+    pub fn remote_addr(&self) -> std::net::SocketAddr {
+        self.peer_addr
+            .tcp()
+            .unwrap_or_else(|| (std::net::Ipv4Addr::UNSPECIFIED, 0).into())
+    }
+
+    /// Get the address of the client, as reported by the PROXY protocol header if it sent one
+    ///
+    /// Otherwise, this is the address of the client that connected to us directly, if it connected over TCP.
+    pub fn client_addr(&self) -> Option<std::net::SocketAddr> {
+        self.proxy_header
+            .as_ref()
+            .and_then(|header| header.source)
+            .or_else(|| self.peer_addr.tcp())
+    }
+
+    /// Get the PROXY protocol header sent at the start of the connection
+    ///
+    /// Only set if PROXY protocol is enabled with `HyperHttpOrHttpsAcceptor::with_proxy_protocol` and the client is
+    /// one of the trusted sources
+    pub const fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.proxy_header.as_ref()
+    }
+
+    /// Get the address of the connected client, whichever kind of listener it connected to
+    pub const fn peer_addr(&self) -> &PeerAddr {
+        &self.peer_addr
+    }
+
+    /// Check whether the connection is encrypted with TLS
+    ///
+    /// Useful to tell the two apart when accepting both HTTP and HTTPS on the same listener
+    pub const fn is_https(&self) -> bool {
+        matches!(self.kind, ConnKind::Https(_))
+    }
+
+    /// Get a reference to the underlying stream, such as the `TcpStream`
+    ///
+    /// Useful for reading socket options or details of the connection. Reading from or writing to it directly would
+    /// corrupt the connection, especially if it's encrypted.
+    pub fn get_ref(&self) -> &IO {
+        match &self.kind {
+            ConnKind::Http(stream) => stream.get_ref(),
+            ConnKind::Https(tls) => tls.get_ref().0.get_ref(),
+        }
+    }
+
+    /// Get the index of the listener that accepted this connection
+    ///
+    /// Listeners are numbered in the order they were added to the `HyperHttpOrHttpsAcceptor`,
+    /// starting at 0 for the one given to the constructor
+    pub const fn listener_index(&self) -> usize {
+        self.listener_index
+    }
+
+    /// Get the `tracing` span covering this connection, which the handshake span is a child of
+    ///
+    /// Enter it (or instrument futures with it) while serving the connection so that spans for its requests are
+    /// nested under it. `HyperHttpOrHttpsAcceptor::serve` already does this for every connection.
+    #[cfg(feature = "tracing")]
+    pub const fn span(&self) -> &tracing::Span {
+        &self.span
     }
 }
 
-impl AsyncRead for HttpOrHttpsConnection {
+impl<IO: AsyncRead + AsyncWrite + Unpin> AsyncRead for HttpOrHttpsConnection<IO> {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         match &mut self.kind {
-            ConnKind::Http(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            ConnKind::Http(stream) => Pin::new(stream).poll_read(cx, buf),
             ConnKind::Https(tls) => Pin::new(tls).poll_read(cx, buf),
         }
     }
 }
 
-impl AsyncWrite for HttpOrHttpsConnection {
+impl<IO: AsyncRead + AsyncWrite + Unpin> AsyncWrite for HttpOrHttpsConnection<IO> {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, std::io::Error>> {
         match &mut self.kind {
-            ConnKind::Http(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            ConnKind::Http(stream) => Pin::new(stream).poll_write(cx, buf),
             ConnKind::Https(tls) => Pin::new(tls).poll_write(cx, buf),
         }
     }
@@ -54,7 +132,7 @@ impl AsyncWrite for HttpOrHttpsConnection {
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
         match &mut self.kind {
-            ConnKind::Http(tcp) => Pin::new(tcp).poll_flush(cx),
+            ConnKind::Http(stream) => Pin::new(stream).poll_flush(cx),
             ConnKind::Https(tls) => Pin::new(tls).poll_flush(cx),
         }
     }
@@ -64,7 +142,7 @@ impl AsyncWrite for HttpOrHttpsConnection {
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
         match &mut self.kind {
-            ConnKind::Http(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            ConnKind::Http(stream) => Pin::new(stream).poll_shutdown(cx),
             ConnKind::Https(tls) => Pin::new(tls).poll_shutdown(cx),
         }
     }
@@ -0,0 +1,106 @@
+use std::time::Duration;
+use tokio_rustls::rustls;
+
+use crate::{AcceptorError, PeerAddr, ProxyHeaderError};
+
+/// Something that happened while accepting connections
+///
+/// Reported to the handler set with `HyperHttpOrHttpsAcceptor::with_event_handler`
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum AcceptorEvent<'a> {
+    /// A connection was dropped because its PROXY protocol header or TLS handshake failed or timed out, or because an
+    /// HTTP client didn't send anything before the first-byte timeout
+    HandshakeFailed(&'a HandshakeFailure),
+    /// Accepting a new connection from a listener failed
+    AcceptFailed(&'a AcceptFailure),
+}
+
+pub type EventHandler = Box<dyn Fn(AcceptorEvent<'_>) + Send + Sync>;
+
+/// Details about a TLS handshake (or reading a PROXY protocol header) that failed
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct HandshakeFailure {
+    /// The address of the client
+    pub peer_addr: PeerAddr,
+    /// The index of the listener that accepted the connection
+    pub listener_index: usize,
+    /// How long after the connection was accepted the handshake failed
+    pub elapsed: Duration,
+    /// What went wrong
+    pub kind: HandshakeFailureKind,
+}
+
+/// Details about a listener failing to accept a connection
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct AcceptFailure {
+    /// The index of the listener that failed
+    pub listener_index: usize,
+    /// The error from the listener
+    pub error: std::io::Error,
+    /// How long the listener will wait before accepting again, if the error means it's out of resources
+    ///
+    /// Only set if backoff is enabled with `HyperHttpOrHttpsAcceptor::with_accept_backoff`, in which case the error
+    /// isn't yielded by the acceptor.
+    pub retry_in: Option<Duration>,
+}
+
+/// The reason a TLS handshake failed
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum HandshakeFailureKind {
+    /// The handshake didn't finish before the handshake timeout, or an HTTP client didn't send anything before the
+    /// first-byte timeout
+    Timeout,
+    /// Reading from or writing to the client failed, including the client closing the connection
+    Io(std::io::Error),
+    /// The TLS handshake itself failed
+    ///
+    /// Alerts sent by the client (such as an unknown CA) show up as `rustls::Error::AlertReceived`, while problems
+    /// on our side (such as no shared cipher suite or protocol version) show up as `rustls::Error::PeerIncompatible`
+    /// or `rustls::Error::PeerMisbehaved`.
+    Tls(rustls::Error),
+    /// The PROXY protocol header was invalid, or didn't arrive in time
+    ProxyHeader(ProxyHeaderError),
+    /// Reading the first byte from a client of an HTTP listener failed, such as the connection being reset
+    ///
+    /// Only reported as an event, like timeouts, since it's a problem with one client rather than the listener.
+    FirstByte(std::io::Error),
+}
+
+impl From<std::io::Error> for HandshakeFailureKind {
+    fn from(err: std::io::Error) -> Self {
+        // tokio-rustls wraps errors from rustls in an io::Error
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            .cloned()
+            .map_or_else(|| Self::Io(err), Self::Tls)
+    }
+}
+
+impl HandshakeFailure {
+    pub(crate) const fn is_timeout(&self) -> bool {
+        matches!(
+            self.kind,
+            HandshakeFailureKind::Timeout
+                | HandshakeFailureKind::ProxyHeader(ProxyHeaderError::Timeout)
+        )
+    }
+
+    // Timeouts and HTTP clients failing before their first byte are only reported as events, everything else is also
+    // yielded by the acceptor
+    pub(crate) fn into_error(self) -> Option<AcceptorError> {
+        match self.kind {
+            HandshakeFailureKind::Timeout
+            | HandshakeFailureKind::ProxyHeader(ProxyHeaderError::Timeout)
+            | HandshakeFailureKind::FirstByte(_) => None,
+            HandshakeFailureKind::Io(err) => Some(AcceptorError::TlsHandshake(err)),
+            HandshakeFailureKind::Tls(err) => Some(AcceptorError::TlsHandshake(
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+            )),
+            HandshakeFailureKind::ProxyHeader(err) => Some(AcceptorError::ProxyHeader(err)),
+        }
+    }
+}
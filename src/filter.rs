@@ -0,0 +1,163 @@
+use std::net::IpAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::cidr::Cidr;
+use crate::listener::PeerAddr;
+
+/// Rules deciding which IP addresses are allowed to connect
+///
+/// An address is allowed if it isn't in any denied block, and is in one of the allowed blocks (if any blocks are
+/// allowed at all). An empty filter allows every address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Create a filter that allows every address
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    /// Allow addresses in `cidr`, which means addresses that aren't in any allowed block are denied
+    #[must_use]
+    pub fn allow(mut self, cidr: Cidr) -> Self {
+        self.allow.push(cidr);
+        self
+    }
+
+    /// Deny addresses in `cidr`, even if they're also in an allowed block
+    #[must_use]
+    pub fn deny(mut self, cidr: Cidr) -> Self {
+        self.deny.push(cidr);
+        self
+    }
+
+    /// Check whether `ip` is allowed to connect
+    #[must_use]
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+/// A handle to change the [`IpFilter`] of a `HyperHttpOrHttpsAcceptor` while it's being used
+///
+/// Get one with `HyperHttpOrHttpsAcceptor::ip_filter_handle`. New rules apply to connections accepted after they're
+/// set, connections that are already open aren't affected.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilterHandle {
+    filter: Arc<RwLock<IpFilter>>,
+}
+
+impl IpFilterHandle {
+    /// Replace the rules
+    pub fn set(&self, filter: IpFilter) {
+        *self.filter.write().unwrap_or_else(PoisonError::into_inner) = filter;
+    }
+
+    /// Get the current rules
+    #[must_use]
+    pub fn get(&self) -> IpFilter {
+        self.filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Clients that didn't connect over IP (like over a Unix socket) aren't filtered
+    pub(crate) fn allows(&self, peer_addr: &PeerAddr) -> bool {
+        peer_addr.tcp().is_none_or(|addr| {
+            self.filter
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_allowed(addr.ip())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_allows_everything() {
+        let filter = IpFilter::new();
+        assert!(filter.is_allowed(ip("10.0.0.1")));
+        assert!(filter.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allow_list() {
+        let filter = IpFilter::new()
+            .allow(cidr("10.0.0.0/8"))
+            .allow(cidr("2001:db8::/32"));
+        assert!(filter.is_allowed(ip("10.1.2.3")));
+        assert!(filter.is_allowed(ip("2001:db8::1")));
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+        assert!(!filter.is_allowed(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn deny_list_without_allow_list() {
+        let filter = IpFilter::new().deny(cidr("192.0.2.0/24"));
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+        assert!(filter.is_allowed(ip("192.0.3.1")));
+        assert!(filter.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let filter = IpFilter::new()
+            .allow(cidr("10.0.0.0/8"))
+            .deny(cidr("10.0.0.0/24"));
+        assert!(filter.is_allowed(ip("10.0.1.1")));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+
+        // Even when the denied block is the same as the allowed one
+        let filter = IpFilter::new()
+            .allow(cidr("10.0.0.1"))
+            .deny(cidr("10.0.0.1"));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn ipv4_blocks_match_ipv4_mapped() {
+        let filter = IpFilter::new().allow(cidr("10.0.0.0/8"));
+        assert!(filter.is_allowed(ip("::ffff:10.1.2.3")));
+        assert!(!filter.is_allowed(ip("::ffff:192.0.2.1")));
+
+        let filter = IpFilter::new().deny(cidr("192.0.2.0/24"));
+        assert!(!filter.is_allowed(ip("::ffff:192.0.2.1")));
+    }
+
+    #[test]
+    fn handle() {
+        let handle = IpFilterHandle::default();
+        let addr = PeerAddr::Tcp("192.0.2.1:1234".parse().unwrap());
+        assert!(handle.allows(&addr));
+
+        let filter = IpFilter::new().deny(cidr("192.0.2.0/24"));
+        // Clones share the same rules
+        let other = handle.clone();
+        other.set(filter.clone());
+        assert_eq!(handle.get(), filter);
+        assert!(!handle.allows(&addr));
+
+        handle.set(IpFilter::new());
+        assert!(handle.allows(&addr));
+    }
+}
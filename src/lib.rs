@@ -3,9 +3,26 @@
 
 //! This library lets you easily create a Hyper acceptor that be configured to either accept HTTP or HTTPS connections.
 //! This is useful for applications that users will self-host, and have the option to run as HTTP or provide their own HTTPS certificates.
-//! **Note: HTTP and HTTPS cannot be accepted at the same time, you decide which one to use when creating the acceptor.**
+//! You decide which one to use when creating the acceptor, or use [`HyperHttpOrHttpsAcceptor::new_http_and_https`] to accept
+//! both on the same port, in which case the first byte sent by each client is used to tell them apart.
+//! A single acceptor can also accept from several listeners, each with its own choice, and feed them all into one hyper `Server`.
+//! Listeners can be TCP, (on Unix) Unix domain sockets, a mix of both through [`AnyListener`], or anything else that
+//! implements the [`Listener`] trait. On Linux, they can also be passed in by systemd socket activation.
+//!
+//! Behind a load balancer, the PROXY protocol can be enabled to recover the address of each client.
+//!
+//! To use hyper 1 instead of hyper 0.14, enable the `hyper1` feature and serve connections with
+//! `HyperHttpOrHttpsAcceptor::serve`. To drive the acceptor with your own loop (or for protocols other than HTTP), accept
+//! connections with [`HyperHttpOrHttpsAcceptor::accept`] or use the acceptor as a `Stream`.
+//!
+//! With the `tracing` feature, every connection gets a `tracing` span (with a child span for its handshake), which
+//! `HttpOrHttpsConnection::span` returns so that spans for its requests can be nested under it.
+//!
+//! If you serve HTTPS, the [`redirect`] module can send clients that connect over plain HTTP to the right place.
+//! Certificates can be replaced without a restart with a [`TlsHandle`], or, with the `watch` feature, reloaded from
+//! their files automatically with a `CertWatcher`.
 //! ## Example
-//! ```
+//! ```no_run
 //! use flexible_hyper_server_tls::*;
 //! use hyper::service::{make_service_fn, service_fn};
 //! use hyper::{Body, Request, Response, Server};
@@ -47,9 +64,45 @@
 //! ```
 
 mod accept;
+mod cidr;
 mod conn;
+mod event;
+mod filter;
+mod limit;
+mod listener;
+mod metrics;
+mod mode;
+mod proxy;
+mod queue;
+pub mod redirect;
+mod rewind;
+#[cfg(feature = "hyper1")]
+mod serve;
+mod shutdown;
+mod socket;
+#[cfg(target_os = "linux")]
+mod systemd;
 pub mod tlsconfig;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "watch")]
+mod watch;
 
 // Export into main library
-pub use accept::{AcceptorError, HyperHttpOrHttpsAcceptor};
+pub use accept::{AcceptorError, ErrorPolicy, HyperHttpOrHttpsAcceptor};
+pub use cidr::{Cidr, CidrError};
 pub use conn::HttpOrHttpsConnection;
+pub use event::{AcceptFailure, AcceptorEvent, HandshakeFailure, HandshakeFailureKind};
+pub use filter::{IpFilter, IpFilterHandle};
+pub use limit::LimitAction;
+pub use listener::{AnyListener, AnyStream, Listener, PeerAddr};
+pub use metrics::{AcceptorMetrics, ListenerMode};
+pub use mode::{ModeHandle, TlsHandle};
+pub use proxy::{ProxyHeader, ProxyHeaderError, ProxyTlv};
+pub use queue::HandshakeQueueHandle;
+pub use shutdown::ShutdownHandle;
+pub use socket::TcpSocketOptions;
+#[cfg(target_os = "linux")]
+pub use systemd::SystemdError;
+#[cfg(feature = "watch")]
+pub use watch::{CertReloadError, CertReloadEvent, CertWatcher};
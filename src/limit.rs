@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use crate::metrics::SharedMetrics;
+
+/// What to do with new connections once the connection limit is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Accept new connections and close them straight away
+    Close,
+    /// Stop accepting new connections, so they wait in the OS backlog until an open connection is closed
+    Backlog,
+}
+
+#[derive(Debug)]
+pub struct ConnectionLimits {
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_per_ip: Option<usize>,
+    pub(crate) on_limit: LimitAction,
+    // Shared with every permit so that they can give their slot back when dropped
+    counts: Arc<Mutex<Counts>>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    open: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    // Woken when a connection is closed, if the acceptor is waiting for room
+    waker: Option<Waker>,
+    // Told about the number of open connections whenever it changes
+    metrics: Option<SharedMetrics>,
+}
+
+/// Proof that a connection was counted towards the limits, which gives its slot back when dropped
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    counts: Arc<Mutex<Counts>>,
+    ip: Option<IpAddr>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_per_ip: None,
+            on_limit: LimitAction::Close,
+            counts: Arc::default(),
+        }
+    }
+}
+
+impl ConnectionLimits {
+    pub fn set_metrics(&self, metrics: SharedMetrics) {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .metrics = Some(metrics);
+    }
+
+    /// Check whether there's room to accept another connection without it being closed
+    ///
+    /// Always ready unless new connections should be left in the backlog. If not ready, the task will be woken up
+    /// when a connection is closed.
+    pub fn poll_ready(&self, cx: &Context<'_>) -> Poll<()> {
+        let Some(max) = self.max_connections else {
+            return Poll::Ready(());
+        };
+        if self.on_limit == LimitAction::Close {
+            return Poll::Ready(());
+        }
+
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        if counts.open < max {
+            Poll::Ready(())
+        } else {
+            counts.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Count a new connection from `ip` (if it has one), or return `None` if it would go over a limit
+    pub fn try_acquire(&self, ip: Option<IpAddr>) -> Option<ConnectionPermit> {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.max_connections.is_some_and(|max| counts.open >= max) {
+            return None;
+        }
+        // Only keep track of addresses if there's a limit for them
+        let ip = match (self.max_per_ip, ip) {
+            (Some(max), Some(ip)) => {
+                let from_ip = counts.per_ip.entry(ip).or_default();
+                if *from_ip >= max {
+                    return None;
+                }
+                *from_ip += 1;
+                Some(ip)
+            }
+            _ => None,
+        };
+
+        counts.open += 1;
+        let (open, metrics) = (counts.open, counts.metrics.clone());
+        drop(counts);
+        if let Some(metrics) = metrics {
+            metrics.connections_open(open);
+        }
+        Some(ConnectionPermit {
+            counts: self.counts.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        counts.open -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(from_ip) = counts.per_ip.get_mut(&ip) {
+                *from_ip -= 1;
+                if *from_ip == 0 {
+                    counts.per_ip.remove(&ip);
+                }
+            }
+        }
+        let (open, metrics, waker) = (counts.open, counts.metrics.clone(), counts.waker.take());
+        drop(counts);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        if let Some(metrics) = metrics {
+            metrics.connections_open(open);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::task::{waker, ArcWake};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn limits(
+        max_connections: Option<usize>,
+        max_per_ip: Option<usize>,
+        on_limit: LimitAction,
+    ) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections,
+            max_per_ip,
+            on_limit,
+            ..ConnectionLimits::default()
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    // Remembers whether it was woken
+    #[derive(Default)]
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn permits_are_released_on_drop() {
+        let limits = limits(Some(2), None, LimitAction::Close);
+        let first = limits.try_acquire(None).unwrap();
+        let second = limits.try_acquire(None).unwrap();
+        assert!(limits.try_acquire(None).is_none());
+
+        drop(first);
+        let third = limits.try_acquire(None).unwrap();
+        assert!(limits.try_acquire(None).is_none());
+        drop((second, third));
+        assert_eq!(limits.counts.lock().unwrap().open, 0);
+    }
+
+    #[test]
+    fn counted_per_ip() {
+        let limits = limits(None, Some(1), LimitAction::Close);
+        let first = limits.try_acquire(Some(ip("192.0.2.1"))).unwrap();
+        assert!(limits.try_acquire(Some(ip("192.0.2.1"))).is_none());
+        // Other addresses, and connections without one, have their own room
+        let _other = limits.try_acquire(Some(ip("192.0.2.2"))).unwrap();
+        let _unix = limits.try_acquire(None).unwrap();
+        let _unix = limits.try_acquire(None).unwrap();
+
+        drop(first);
+        assert!(!limits
+            .counts
+            .lock()
+            .unwrap()
+            .per_ip
+            .contains_key(&ip("192.0.2.1")));
+        let _again = limits.try_acquire(Some(ip("192.0.2.1"))).unwrap();
+    }
+
+    #[test]
+    fn total_limit_applies_across_ips() {
+        let limits = limits(Some(1), Some(1), LimitAction::Close);
+        let _first = limits.try_acquire(Some(ip("192.0.2.1"))).unwrap();
+        assert!(limits.try_acquire(Some(ip("192.0.2.2"))).is_none());
+        // A refused connection isn't counted against its address
+        assert!(!limits
+            .counts
+            .lock()
+            .unwrap()
+            .per_ip
+            .contains_key(&ip("192.0.2.2")));
+    }
+
+    #[test]
+    fn always_ready_when_closing() {
+        let limits = limits(Some(1), None, LimitAction::Close);
+        let _permit = limits.try_acquire(None).unwrap();
+        let woken = Arc::new(Woken::default());
+        let waker = waker(woken);
+        assert!(limits.poll_ready(&Context::from_waker(&waker)).is_ready());
+    }
+
+    #[test]
+    fn backlog_wakes_when_permit_dropped() {
+        let limits = limits(Some(1), None, LimitAction::Backlog);
+        let woken = Arc::new(Woken::default());
+        let waker = waker(woken.clone());
+        let cx = Context::from_waker(&waker);
+        assert!(limits.poll_ready(&cx).is_ready());
+
+        let permit = limits.try_acquire(None).unwrap();
+        assert!(limits.poll_ready(&cx).is_pending());
+        assert!(!woken.0.load(Ordering::SeqCst));
+
+        drop(permit);
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert!(limits.poll_ready(&cx).is_ready());
+    }
+}
@@ -0,0 +1,168 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A source of connections that the acceptor can accept from
+///
+/// Implemented for `tokio::net::TcpListener`, `tokio::net::UnixListener` (on Unix) and [`AnyListener`], but it can
+/// also be implemented for anything else that produces streams, like in-memory pipes or instrumented sockets.
+pub trait Listener {
+    /// The stream connecting to each client
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Poll to accept a new connection, returning its stream and the address of the client
+    ///
+    /// # Errors
+    /// Errors if accepting the connection failed. Errors with a kind of `ConnectionRefused`, `ConnectionAborted` or
+    /// `ConnectionReset` are treated as only affecting that connection, see `AcceptorError::is_transient`.
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<(Self::Io, PeerAddr)>>;
+}
+
+/// A listener that accepts connections over either TCP or (on Unix) a Unix domain socket
+///
+/// Useful to accept from both kinds of listener with a single acceptor, which needs all of its listeners to be the
+/// same type. Created from either listener using `From`.
+///
+/// Listening on an abstract Unix socket on Linux works the same as any other Unix socket, bind it with
+/// `std::os::unix::net::UnixListener::bind_addr`, set it to non-blocking and convert it with `UnixListener::from_std`.
+#[derive(Debug)]
+pub enum AnyListener {
+    /// Accept connections over TCP
+    Tcp(tokio::net::TcpListener),
+    /// Accept connections over a Unix domain socket
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+/// The stream yielded by [`AnyListener`]
+#[derive(Debug)]
+pub enum AnyStream {
+    /// A TCP stream
+    Tcp(tokio::net::TcpStream),
+    /// A Unix domain socket stream
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+/// The address of the client on the other end of a connection
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum PeerAddr {
+    /// A client connected over TCP
+    Tcp(std::net::SocketAddr),
+    /// A client connected over a Unix domain socket
+    #[cfg(unix)]
+    Unix {
+        /// The address of the client's socket, which is usually unnamed
+        addr: tokio::net::unix::SocketAddr,
+        /// The credentials of the client process (`SO_PEERCRED`), if the OS could provide them
+        cred: Option<tokio::net::unix::UCred>,
+    },
+    /// A client whose address isn't known, such as one connected through an in-memory pipe
+    Unknown,
+}
+
+impl Listener for tokio::net::TcpListener {
+    type Io = tokio::net::TcpStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<(Self::Io, PeerAddr)>> {
+        Self::poll_accept(self, cx).map_ok(|(stream, addr)| (stream, PeerAddr::Tcp(addr)))
+    }
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<(Self::Io, PeerAddr)>> {
+        Self::poll_accept(self, cx).map_ok(|(stream, addr)| {
+            let cred = stream.peer_cred().ok();
+            (stream, PeerAddr::Unix { addr, cred })
+        })
+    }
+}
+
+impl Listener for AnyListener {
+    type Io = AnyStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<(Self::Io, PeerAddr)>> {
+        match self {
+            Self::Tcp(listener) => Listener::poll_accept(listener, cx)
+                .map_ok(|(stream, addr)| (AnyStream::Tcp(stream), addr)),
+            #[cfg(unix)]
+            Self::Unix(listener) => Listener::poll_accept(listener, cx)
+                .map_ok(|(stream, addr)| (AnyStream::Unix(stream), addr)),
+        }
+    }
+}
+
+impl From<tokio::net::TcpListener> for AnyListener {
+    fn from(listener: tokio::net::TcpListener) -> Self {
+        Self::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::UnixListener> for AnyListener {
+    fn from(listener: tokio::net::UnixListener) -> Self {
+        Self::Unix(listener)
+    }
+}
+
+impl PeerAddr {
+    /// Get the IP address and port of a client connected over TCP
+    #[must_use]
+    pub const fn tcp(&self) -> Option<std::net::SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(*addr),
+            _ => None,
+        }
+    }
+}
+
+impl AsyncRead for AnyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(unix) => Pin::new(unix).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AnyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        match self.get_mut() {
+            Self::Tcp(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(unix) => Pin::new(unix).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        match self.get_mut() {
+            Self::Tcp(tcp) => Pin::new(tcp).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(unix) => Pin::new(unix).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match self.get_mut() {
+            Self::Tcp(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(unix) => Pin::new(unix).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receives numbers about what the acceptor is doing, to be recorded with a metrics library
+///
+/// Set with `HyperHttpOrHttpsAcceptor::with_metrics`. Every method does nothing by default, so only the ones that are
+/// needed have to be implemented. They're called from within the accept loop (and from wherever connections are
+/// dropped), so they should return quickly.
+///
+/// "Handshakes" cover everything that happens to a connection before it's yielded, which is the TLS handshake for
+/// HTTPS, but also reading the PROXY protocol header or waiting for the first byte when those are enabled. Each one is
+/// reported with the mode of the listener it came from, so that HTTP connections waiting for their first byte can be
+/// told apart from TLS handshakes.
+pub trait AcceptorMetrics: Send + Sync {
+    /// A connection was accepted from the listener at `listener_index`
+    ///
+    /// Called before the connection is checked against the IP filter and connection limits, so it may still be closed.
+    fn connection_accepted(&self, listener_index: usize, mode: ListenerMode) {
+        let _ = (listener_index, mode);
+    }
+
+    /// A connection from a listener in `mode` was added to the handshake queue
+    fn handshake_started(&self, mode: ListenerMode) {
+        let _ = mode;
+    }
+
+    /// A handshake for a listener in `mode` finished after `elapsed` and the connection was yielded
+    fn handshake_succeeded(&self, mode: ListenerMode, elapsed: Duration) {
+        let _ = (mode, elapsed);
+    }
+
+    /// A handshake for a listener in `mode` failed after `elapsed` and the connection was dropped
+    fn handshake_failed(&self, mode: ListenerMode, elapsed: Duration) {
+        let _ = (mode, elapsed);
+    }
+
+    /// A handshake for a listener in `mode` timed out after `elapsed` and the connection was dropped
+    fn handshake_timed_out(&self, mode: ListenerMode, elapsed: Duration) {
+        let _ = (mode, elapsed);
+    }
+
+    /// The size of the handshake queue changed
+    ///
+    /// `handshakes` is the number of connections from HTTPS listeners in the queue, and `http_waits` is the number from
+    /// HTTP listeners waiting for their first byte or PROXY protocol header. They're the same numbers as given by
+    /// `HandshakeQueueHandle`, and are limited separately by `HyperHttpOrHttpsAcceptor::with_max_handshakes`.
+    fn handshake_queue_len(&self, handshakes: usize, http_waits: usize) {
+        let _ = (handshakes, http_waits);
+    }
+
+    /// The number of open connections changed to `count`
+    ///
+    /// Connections are counted from when they're accepted (including during the handshake) until the
+    /// `HttpOrHttpsConnection` is dropped, the same as for `HyperHttpOrHttpsAcceptor::with_max_connections`.
+    fn connections_open(&self, count: usize) {
+        let _ = count;
+    }
+}
+
+/// Which kind of connections a listener accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ListenerMode {
+    /// Only HTTP
+    Http,
+    /// Only HTTPS
+    Https,
+    /// Both HTTP and HTTPS on the same listener
+    HttpAndHttps,
+}
+
+// Shared between the acceptor and every connection, with a `Debug` impl so that the types holding it can derive theirs
+#[derive(Clone)]
+pub struct SharedMetrics(pub Arc<dyn AcceptorMetrics>);
+
+impl std::fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedMetrics")
+    }
+}
+
+impl std::ops::Deref for SharedMetrics {
+    type Target = dyn AcceptorMetrics;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
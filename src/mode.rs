@@ -0,0 +1,165 @@
+use std::sync::{Arc, PoisonError, RwLock};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::metrics::ListenerMode;
+
+#[derive(Clone)]
+pub enum AcceptorKind {
+    Http,
+    Https {
+        tls_acceptor: TlsAcceptor,
+        timeout: std::time::Duration,
+        // Whether to check the first byte of each connection and let plain HTTP through
+        allow_http: bool,
+    },
+}
+
+impl AcceptorKind {
+    pub const fn mode(&self) -> ListenerMode {
+        match self {
+            Self::Http => ListenerMode::Http,
+            Self::Https {
+                allow_http: false, ..
+            } => ListenerMode::Https,
+            Self::Https {
+                allow_http: true, ..
+            } => ListenerMode::HttpAndHttps,
+        }
+    }
+}
+
+/// A handle to switch a listener of a `HyperHttpOrHttpsAcceptor` between HTTP and HTTPS while it's being used
+///
+/// Get one with `HyperHttpOrHttpsAcceptor::mode_handle`. The new mode applies to connections accepted after it's
+/// set, connections that are already open or still in their handshake aren't affected.
+#[derive(Clone)]
+pub struct ModeHandle {
+    kind: Arc<RwLock<AcceptorKind>>,
+}
+
+impl ModeHandle {
+    pub(crate) fn new(kind: AcceptorKind) -> Self {
+        Self {
+            kind: Arc::new(RwLock::new(kind)),
+        }
+    }
+
+    /// Only accept HTTP connections
+    pub fn set_http(&self) {
+        self.set(AcceptorKind::Http);
+    }
+
+    /// Only accept HTTPS connections, encrypted using `tls_acceptor`
+    ///
+    /// See `HyperHttpOrHttpsAcceptor::new_https`
+    pub fn set_https(&self, tls_acceptor: TlsAcceptor, handshake_timeout: std::time::Duration) {
+        self.set(AcceptorKind::Https {
+            tls_acceptor,
+            timeout: handshake_timeout,
+            allow_http: false,
+        });
+    }
+
+    /// Accept both HTTP and HTTPS connections, telling them apart by the first byte
+    ///
+    /// See `HyperHttpOrHttpsAcceptor::new_http_and_https`
+    pub fn set_http_and_https(
+        &self,
+        tls_acceptor: TlsAcceptor,
+        handshake_timeout: std::time::Duration,
+    ) {
+        self.set(AcceptorKind::Https {
+            tls_acceptor,
+            timeout: handshake_timeout,
+            allow_http: true,
+        });
+    }
+
+    /// Get which kind of connections the listener currently accepts
+    #[must_use]
+    pub fn mode(&self) -> ListenerMode {
+        self.kind
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .mode()
+    }
+
+    fn set(&self, kind: AcceptorKind) {
+        *self.kind.write().unwrap_or_else(PoisonError::into_inner) = kind;
+    }
+
+    pub(crate) fn tls_handle(&self) -> TlsHandle {
+        TlsHandle {
+            kind: self.kind.clone(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> AcceptorKind {
+        self.kind
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl std::fmt::Debug for ModeHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModeHandle")
+            .field("mode", &self.mode())
+            .finish()
+    }
+}
+
+/// A handle to replace the `TlsAcceptor` of an HTTPS listener while it's being used, such as to load a renewed
+/// certificate
+///
+/// Get one with `HyperHttpOrHttpsAcceptor::tls_handle`. Handshakes started after the `TlsAcceptor` is replaced use the
+/// new certificate and settings, handshakes in progress and connections that are already open aren't affected.
+///
+/// Only listeners that accept HTTPS have a `TlsAcceptor` to replace. To turn HTTPS on for a listener that only
+/// accepts HTTP, use [`ModeHandle::set_https`] instead.
+#[derive(Clone)]
+pub struct TlsHandle {
+    kind: Arc<RwLock<AcceptorKind>>,
+}
+
+impl TlsHandle {
+    /// Replace the `TlsAcceptor`, keeping the handshake timeout and whether plain HTTP is also accepted
+    ///
+    /// Returns `false` (and does nothing) if the listener currently only accepts HTTP.
+    #[must_use]
+    pub fn set(&self, tls_acceptor: TlsAcceptor) -> bool {
+        match &mut *self.kind.write().unwrap_or_else(PoisonError::into_inner) {
+            AcceptorKind::Http => false,
+            AcceptorKind::Https {
+                tls_acceptor: current,
+                ..
+            } => {
+                *current = tls_acceptor;
+                true
+            }
+        }
+    }
+
+    /// Replace the `TlsAcceptor` with one using `config`, see [`Self::set`]
+    #[must_use]
+    pub fn set_config(&self, config: Arc<ServerConfig>) -> bool {
+        self.set(TlsAcceptor::from(config))
+    }
+
+    /// Get the current `TlsAcceptor`, or `None` if the listener currently only accepts HTTP
+    #[must_use]
+    pub fn get(&self) -> Option<TlsAcceptor> {
+        match &*self.kind.read().unwrap_or_else(PoisonError::into_inner) {
+            AcceptorKind::Http => None,
+            AcceptorKind::Https { tls_acceptor, .. } => Some(tls_acceptor.clone()),
+        }
+    }
+}
+
+impl std::fmt::Debug for TlsHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsHandle").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,479 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::cidr::Cidr;
+use crate::listener::PeerAddr;
+
+/// The PROXY protocol header sent by a load balancer at the start of a connection
+///
+/// See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt> for the details of both versions
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ProxyHeader {
+    /// The version of the protocol the header was sent with, either 1 or 2
+    pub version: u8,
+    /// The address of the client that connected to the load balancer
+    ///
+    /// Not set if the load balancer made the connection itself (like for health checks), or if the client didn't
+    /// connect over TCP.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to on the load balancer, set along with `source`
+    pub destination: Option<SocketAddr>,
+    /// Extra information about the connection, only sent with version 2
+    pub tlvs: Vec<ProxyTlv>,
+}
+
+/// A type-length-value field from a version 2 PROXY protocol header
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ProxyTlv {
+    /// The type of the field, like `0x02` for the authority (the SNI sent by the client)
+    pub kind: u8,
+    /// The raw value of the field
+    pub value: Vec<u8>,
+}
+
+/// Error when reading a PROXY protocol header
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ProxyHeaderError {
+    /// Reading the header from the connection failed, including the connection closing part way through
+    #[error("failed to read PROXY protocol header")]
+    Io(#[from] std::io::Error),
+    /// The connection didn't start with a valid header
+    #[error("invalid PROXY protocol header")]
+    Invalid,
+    /// The header wasn't sent before the PROXY protocol timeout
+    #[error("timed out waiting for PROXY protocol header")]
+    Timeout,
+}
+
+// The types of the TLVs with helper methods
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+
+impl ProxyHeader {
+    /// Get the value of the first TLV of type `kind`
+    #[must_use]
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_slice())
+    }
+
+    /// Get the host name the client asked for, usually the SNI it sent to the load balancer
+    #[must_use]
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(PP2_TYPE_AUTHORITY)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// Get the unique ID the load balancer gave the connection
+    #[must_use]
+    pub fn unique_id(&self) -> Option<&[u8]> {
+        self.tlv(PP2_TYPE_UNIQUE_ID)
+    }
+}
+
+#[derive(Debug)]
+pub struct ProxyProtocol {
+    pub(crate) timeout: std::time::Duration,
+    pub(crate) trusted_sources: Vec<Cidr>,
+}
+
+impl ProxyProtocol {
+    // Get how long to wait for a header from this client, or `None` if it isn't trusted to send one
+    pub fn timeout_for(&self, peer_addr: &PeerAddr) -> Option<std::time::Duration> {
+        // Clients that didn't connect over IP (like over a Unix socket) can only be local, so they're trusted
+        let trusted = peer_addr.tcp().is_none_or(|addr| {
+            self.trusted_sources
+                .iter()
+                .any(|cidr| cidr.contains(addr.ip()))
+        });
+        trusted.then_some(self.timeout)
+    }
+}
+
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// The longest a version 1 header can be, including the prefix and the CRLF
+const V1_MAX_LEN: usize = 107;
+
+// Read a header from the start of the stream, without reading anything past the end of it
+pub async fn read_header(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<ProxyHeader, ProxyHeaderError> {
+    let mut start = [0; 6];
+    stream.read_exact(&mut start).await?;
+    if &start == V1_PREFIX {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..6] {
+        read_v2(stream).await
+    } else {
+        Err(ProxyHeaderError::Invalid)
+    }
+}
+
+async fn read_v1(stream: &mut (impl AsyncRead + Unpin)) -> Result<ProxyHeader, ProxyHeaderError> {
+    // Read a byte at a time, since the length isn't known up front and anything after the header belongs to the client
+    let max_len = V1_MAX_LEN - V1_PREFIX.len();
+    let mut line = Vec::with_capacity(max_len);
+    while !line.ends_with(b"\r\n") {
+        if line.len() == max_len {
+            return Err(ProxyHeaderError::Invalid);
+        }
+        line.push(stream.read_u8().await?);
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line).map_err(|_| ProxyHeaderError::Invalid)?;
+    let mut parts = line.split(' ');
+    let (source, destination) = match parts.next() {
+        // The rest of the line is meant to be ignored
+        Some("UNKNOWN") => (None, None),
+        Some(protocol @ ("TCP4" | "TCP6")) => {
+            let fields: Vec<_> = parts.collect();
+            let [source_ip, destination_ip, source_port, destination_port] = fields[..] else {
+                return Err(ProxyHeaderError::Invalid);
+            };
+            let parse_ip = |ip: &str| -> Result<IpAddr, ProxyHeaderError> {
+                let ip = if protocol == "TCP4" {
+                    ip.parse::<Ipv4Addr>().map(IpAddr::V4)
+                } else {
+                    ip.parse::<Ipv6Addr>().map(IpAddr::V6)
+                };
+                ip.map_err(|_| ProxyHeaderError::Invalid)
+            };
+            let parse_port =
+                |port: &str| port.parse::<u16>().map_err(|_| ProxyHeaderError::Invalid);
+            (
+                Some(SocketAddr::new(
+                    parse_ip(source_ip)?,
+                    parse_port(source_port)?,
+                )),
+                Some(SocketAddr::new(
+                    parse_ip(destination_ip)?,
+                    parse_port(destination_port)?,
+                )),
+            )
+        }
+        _ => return Err(ProxyHeaderError::Invalid),
+    };
+
+    Ok(ProxyHeader {
+        version: 1,
+        source,
+        destination,
+        tlvs: Vec::new(),
+    })
+}
+
+async fn read_v2(stream: &mut (impl AsyncRead + Unpin)) -> Result<ProxyHeader, ProxyHeaderError> {
+    // The rest of the signature, then the version and command, the address family and the length of the rest
+    let mut fixed = [0; 10];
+    stream.read_exact(&mut fixed).await?;
+    if fixed[..6] != V2_SIGNATURE[6..] || fixed[6] >> 4 != 2 {
+        return Err(ProxyHeaderError::Invalid);
+    }
+    let is_local = match fixed[6] & 0x0F {
+        0x0 => true,
+        0x1 => false,
+        _ => return Err(ProxyHeaderError::Invalid),
+    };
+    let family = fixed[7] >> 4;
+    // Only connections over a stream (like TCP) can be passed along, UNSPEC is sent with LOCAL
+    if !matches!(fixed[7] & 0x0F, 0x0 | 0x1) {
+        return Err(ProxyHeaderError::Invalid);
+    }
+    let len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+
+    let mut rest = vec![0; len];
+    stream.read_exact(&mut rest).await?;
+
+    let addr_len = match family {
+        // AF_UNSPEC
+        0x0 => 0,
+        // AF_INET
+        0x1 => 12,
+        // AF_INET6
+        0x2 => 36,
+        // AF_UNIX
+        0x3 => 216,
+        _ => return Err(ProxyHeaderError::Invalid),
+    };
+    if rest.len() < addr_len {
+        return Err(ProxyHeaderError::Invalid);
+    }
+    let (addrs, mut tlvs_data) = rest.split_at(addr_len);
+
+    // Addresses from a LOCAL connection are meant to be ignored, and Unix socket addresses aren't useful to us
+    let (source, destination) = match family {
+        0x1 if !is_local => {
+            let ip = |i: usize| IpAddr::from([addrs[i], addrs[i + 1], addrs[i + 2], addrs[i + 3]]);
+            let port = |i: usize| u16::from_be_bytes([addrs[i], addrs[i + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(8))),
+                Some(SocketAddr::new(ip(4), port(10))),
+            )
+        }
+        0x2 if !is_local => {
+            let ip = |i: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addrs[i..i + 16]);
+                IpAddr::from(octets)
+            };
+            let port = |i: usize| u16::from_be_bytes([addrs[i], addrs[i + 1]]);
+            (
+                Some(SocketAddr::new(ip(0), port(32))),
+                Some(SocketAddr::new(ip(16), port(34))),
+            )
+        }
+        _ => (None, None),
+    };
+
+    let mut tlvs = Vec::new();
+    while !tlvs_data.is_empty() {
+        let [kind, len_high, len_low, ref remaining @ ..] = *tlvs_data else {
+            return Err(ProxyHeaderError::Invalid);
+        };
+        let len = usize::from(u16::from_be_bytes([len_high, len_low]));
+        if remaining.len() < len {
+            return Err(ProxyHeaderError::Invalid);
+        }
+        let (value, remaining) = remaining.split_at(len);
+        tlvs.push(ProxyTlv {
+            kind,
+            value: value.to_vec(),
+        });
+        tlvs_data = remaining;
+    }
+
+    Ok(ProxyHeader {
+        version: 2,
+        source,
+        destination,
+        tlvs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read a header from `data`, returning it along with whatever is left for the client
+    async fn read(data: &[u8]) -> (Result<ProxyHeader, ProxyHeaderError>, Vec<u8>) {
+        let mut stream = data;
+        let res = read_header(&mut stream).await;
+        (res, stream.to_vec())
+    }
+
+    fn v2(command: u8, family_transport: u8, rest: &[u8]) -> Vec<u8> {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.push(0x20 | command);
+        data.push(family_transport);
+        data.extend_from_slice(&u16::try_from(rest.len()).unwrap().to_be_bytes());
+        data.extend_from_slice(rest);
+        data
+    }
+
+    fn v2_tcp4_addrs() -> Vec<u8> {
+        let mut addrs = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        addrs.extend_from_slice(&56324_u16.to_be_bytes());
+        addrs.extend_from_slice(&443_u16.to_be_bytes());
+        addrs
+    }
+
+    fn assert_invalid(res: &Result<ProxyHeader, ProxyHeaderError>) {
+        assert!(matches!(res, Err(ProxyHeaderError::Invalid)), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (res, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+        let header = res.unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert!(header.tlvs.is_empty());
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (res, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+        let header = res.unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("[2001:db8::2]:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (res, rest) = read(b"PROXY UNKNOWN anything goes here\r\nGET /").await;
+        let header = res.unwrap();
+        assert_eq!(header.source, None);
+        assert_eq!(header.destination, None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_invalid() {
+        for data in [
+            &b"PROXY TCP5 192.0.2.1 198.51.100.1 56324 443\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n",
+            b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 80\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 65536\r\n",
+            b"PROXY TCP4  192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY \xff\r\n",
+        ] {
+            assert_invalid(&read(data).await.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_truncated() {
+        let (res, _) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").await;
+        assert!(matches!(res, Err(ProxyHeaderError::Io(_))), "{res:?}");
+        // Only a CRLF ends the line
+        let (res, _) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\n").await;
+        assert!(matches!(res, Err(ProxyHeaderError::Io(_))), "{res:?}");
+        let (res, _) = read(b"PROX").await;
+        assert!(matches!(res, Err(ProxyHeaderError::Io(_))), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn v1_max_len() {
+        let header = |len: usize| {
+            let mut data = b"PROXY UNKNOWN ".to_vec();
+            data.resize(len - 2, b'x');
+            data.extend_from_slice(b"\r\n");
+            data
+        };
+        assert!(read(&header(V1_MAX_LEN)).await.0.is_ok());
+        assert_invalid(&read(&header(V1_MAX_LEN + 1)).await.0);
+
+        // Stops reading at the limit, rather than waiting for a CRLF that never comes
+        let (res, rest) = read(&[b"PROXY ".as_slice(), &[b'x'; 200]].concat()).await;
+        assert_invalid(&res);
+        assert_eq!(rest.len(), 200 - (V1_MAX_LEN - V1_PREFIX.len()));
+    }
+
+    #[tokio::test]
+    async fn not_a_header() {
+        assert_invalid(&read(b"GET / HTTP/1.1\r\n\r\n").await.0);
+        assert_invalid(
+            &read(b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03")
+                .await
+                .0,
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4_with_tlvs() {
+        let mut rest = v2_tcp4_addrs();
+        rest.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0, 11]);
+        rest.extend_from_slice(b"example.com");
+        rest.extend_from_slice(&[PP2_TYPE_UNIQUE_ID, 0, 3, 1, 2, 3]);
+        rest.extend_from_slice(&[0xE0, 0, 0]);
+        let mut data = v2(0x1, 0x11, &rest);
+        data.extend_from_slice(b"GET /");
+
+        let (res, rest) = read(&data).await;
+        let header = res.unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(header.tlvs.len(), 3);
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.unique_id(), Some(&[1, 2, 3][..]));
+        assert_eq!(header.tlv(0xE0), Some(&[][..]));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6() {
+        let mut rest = Vec::new();
+        rest.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        rest.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        rest.extend_from_slice(&56324_u16.to_be_bytes());
+        rest.extend_from_slice(&443_u16.to_be_bytes());
+
+        let header = read(&v2(0x1, 0x21, &rest)).await.0.unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("[2001:db8::2]:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        // Addresses sent with LOCAL are ignored
+        let header = read(&v2(0x0, 0x11, &v2_tcp4_addrs())).await.0.unwrap();
+        assert_eq!(header.source, None);
+        assert_eq!(header.destination, None);
+
+        let header = read(&v2(0x0, 0x00, &[])).await.0.unwrap();
+        assert_eq!(header.source, None);
+    }
+
+    #[tokio::test]
+    async fn v2_unix() {
+        let header = read(&v2(0x1, 0x31, &[0; 216])).await.0.unwrap();
+        assert_eq!(header.source, None);
+        assert_eq!(header.destination, None);
+    }
+
+    #[tokio::test]
+    async fn v2_invalid() {
+        let mut tlv_too_long = v2_tcp4_addrs();
+        tlv_too_long.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0, 12]);
+        tlv_too_long.extend_from_slice(b"example.com");
+        let mut tlv_truncated = v2_tcp4_addrs();
+        tlv_truncated.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0]);
+
+        for data in [
+            // A TLV longer than what's left of the header
+            v2(0x1, 0x11, &tlv_too_long),
+            v2(0x1, 0x11, &tlv_truncated),
+            // Too short for the addresses
+            v2(0x1, 0x11, &v2_tcp4_addrs()[..11]),
+            // DGRAM and unknown transports
+            v2(0x1, 0x12, &v2_tcp4_addrs()),
+            v2(0x1, 0x13, &v2_tcp4_addrs()),
+            // Unknown family and command
+            v2(0x1, 0x41, &v2_tcp4_addrs()),
+            v2(0x2, 0x11, &v2_tcp4_addrs()),
+        ] {
+            assert_invalid(&read(&data).await.0);
+        }
+
+        let mut wrong_version = v2(0x1, 0x11, &v2_tcp4_addrs());
+        wrong_version[12] = 0x11;
+        assert_invalid(&read(&wrong_version).await.0);
+        let mut wrong_signature = v2(0x1, 0x11, &v2_tcp4_addrs());
+        wrong_signature[10] = b'X';
+        assert_invalid(&read(&wrong_signature).await.0);
+    }
+
+    #[tokio::test]
+    async fn v2_truncated() {
+        let data = v2(0x1, 0x11, &v2_tcp4_addrs());
+        let (res, _) = read(&data[..data.len() - 1]).await;
+        assert!(matches!(res, Err(ProxyHeaderError::Io(_))), "{res:?}");
+        let (res, _) = read(&data[..14]).await;
+        assert!(matches!(res, Err(ProxyHeaderError::Io(_))), "{res:?}");
+    }
+}
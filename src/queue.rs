@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A handle to see how many connections a `HyperHttpOrHttpsAcceptor` has in its handshake queue while it's being used
+///
+/// Get one with `HyperHttpOrHttpsAcceptor::handshake_queue_handle`. Connections from HTTPS listeners are counted as
+/// TLS handshakes, and connections from HTTP listeners waiting for their first byte or PROXY protocol header are
+/// counted separately.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeQueueHandle {
+    handshakes: Arc<AtomicUsize>,
+    http_waits: Arc<AtomicUsize>,
+}
+
+impl HandshakeQueueHandle {
+    /// Get the number of TLS handshakes currently in progress
+    #[must_use]
+    pub fn pending_handshakes(&self) -> usize {
+        self.handshakes.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of connections from HTTP listeners waiting for their first byte or PROXY protocol header
+    #[must_use]
+    pub fn pending_http_waits(&self) -> usize {
+        self.http_waits.load(Ordering::Relaxed)
+    }
+
+    // Count a handshake until the returned slot is dropped
+    pub(crate) fn start_handshake(&self) -> QueueSlot {
+        QueueSlot::new(&self.handshakes)
+    }
+
+    // Count a connection from an HTTP listener until the returned slot is dropped
+    pub(crate) fn start_http_wait(&self) -> QueueSlot {
+        QueueSlot::new(&self.http_waits)
+    }
+}
+
+// Held by a handshake for as long as it's in the queue, whether it finishes, fails or is dropped
+pub struct QueueSlot(Arc<AtomicUsize>);
+
+impl QueueSlot {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count.clone())
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,298 @@
+//! Provides a hyper service that redirects plain HTTP requests to HTTPS.
+//!
+//! This is meant to be served on the HTTP port next to an HTTPS acceptor, so that users who type `http://` still end up
+//! in the right place. Paths that need to stay reachable over plain HTTP (like ACME challenges) can be passed through to
+//! another service.
+//! ## Example
+//! ```no_run
+//! use flexible_hyper_server_tls::redirect::HttpsRedirect;
+//! use flexible_hyper_server_tls::*;
+//! use hyper::service::make_service_fn;
+//! use hyper::Server;
+//! use std::convert::Infallible;
+//! use tokio::net::TcpListener;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let listener = TcpListener::bind("0.0.0.0:80").await.unwrap();
+//!     let redirect = HttpsRedirect::new().pass_through("/.well-known/acme-challenge/");
+//!
+//!     let make_svc = make_service_fn(move |_conn: &HttpOrHttpsConnection| {
+//!         let redirect = redirect.clone();
+//!         async { Ok::<_, Infallible>(redirect) }
+//!     });
+//!
+//!     Server::builder(HyperHttpOrHttpsAcceptor::new_http(listener))
+//!         .serve(make_svc)
+//!         .await
+//!         .unwrap();
+//! }
+//! ```
+
+use futures_util::future::{ready, Either, Ready};
+use hyper::http::uri::{Authority, Scheme};
+use hyper::service::Service;
+use hyper::{header, Body, Request, Response, StatusCode, Uri};
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+/// A service that redirects every request to its `https://` equivalent, keeping the host, path and query
+///
+/// Requests for paths that are passed through are handed to the inner service instead.
+#[derive(Debug, Clone)]
+pub struct HttpsRedirect<S = NotFound> {
+    https_port: u16,
+    status: StatusCode,
+    pass_through: Vec<String>,
+    inner: S,
+}
+
+/// A service that answers every request with a 404, used by [`HttpsRedirect`] when no inner service is set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NotFound;
+
+impl HttpsRedirect {
+    /// Create a service that permanently redirects to HTTPS on the default port
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            https_port: 443,
+            status: StatusCode::PERMANENT_REDIRECT,
+            pass_through: Vec::new(),
+            inner: NotFound,
+        }
+    }
+}
+
+impl Default for HttpsRedirect {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> HttpsRedirect<S> {
+    /// Set the port that HTTPS is served on, which is left out of the redirect if it's 443
+    #[must_use]
+    pub const fn https_port(mut self, port: u16) -> Self {
+        self.https_port = port;
+        self
+    }
+
+    /// Set the status code of the redirect
+    ///
+    /// This should be a redirection status, normally either `308 Permanent Redirect` (the default), which makes clients
+    /// keep the request method, or `301 Moved Permanently`, which older clients understand better.
+    #[must_use]
+    pub const fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Pass requests for paths starting with `prefix` through to the inner service instead of redirecting them
+    #[must_use]
+    pub fn pass_through(mut self, prefix: impl Into<String>) -> Self {
+        self.pass_through.push(prefix.into());
+        self
+    }
+
+    /// Set the service that requests which are passed through are sent to
+    ///
+    /// Without one, those requests get a 404.
+    pub fn with_service<T>(self, inner: T) -> HttpsRedirect<T> {
+        HttpsRedirect {
+            https_port: self.https_port,
+            status: self.status,
+            pass_through: self.pass_through,
+            inner,
+        }
+    }
+
+    fn redirect<ReqBody, ResBody: Default>(&self, req: &Request<ReqBody>) -> Response<ResBody> {
+        let mut res = Response::new(ResBody::default());
+        match self.location(req) {
+            Some(location) => {
+                *res.status_mut() = self.status;
+                // A URI is always a valid header value
+                res.headers_mut().insert(
+                    header::LOCATION,
+                    header::HeaderValue::from_str(&location.to_string()).unwrap(),
+                );
+            }
+            None => *res.status_mut() = StatusCode::BAD_REQUEST,
+        }
+        res
+    }
+
+    fn location<B>(&self, req: &Request<B>) -> Option<Uri> {
+        // HTTP/2 requests carry the host in the URI, HTTP/1 requests in the header
+        let authority = match req.uri().authority() {
+            Some(authority) => authority.clone(),
+            None => req
+                .headers()
+                .get(header::HOST)?
+                .to_str()
+                .ok()?
+                .parse::<Authority>()
+                .ok()?,
+        };
+        let authority = if self.https_port == 443 {
+            authority.host().to_owned()
+        } else {
+            format!("{}:{}", authority.host(), self.https_port)
+        };
+
+        Uri::builder()
+            .scheme(Scheme::HTTPS)
+            .authority(authority)
+            .path_and_query(req.uri().path_and_query().map_or("/", |pq| pq.as_str()))
+            .build()
+            .ok()
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpsRedirect<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let path = req.uri().path();
+        if self
+            .pass_through
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+        {
+            Either::Right(self.inner.call(req))
+        } else {
+            Either::Left(ready(Ok(self.redirect(&req))))
+        }
+    }
+}
+
+impl<ReqBody> Service<Request<ReqBody>> for NotFound {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<ReqBody>) -> Self::Future {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        ready(Ok(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, host: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(host) = host {
+            builder = builder.header(header::HOST, host);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    fn call<S>(service: &mut HttpsRedirect<S>, req: Request<Body>) -> Response<Body>
+    where
+        S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+        S::Future: Unpin,
+    {
+        let future = service.call(req);
+        futures_util::FutureExt::now_or_never(future)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn location(res: &Response<Body>) -> &str {
+        res.headers()[header::LOCATION].to_str().unwrap()
+    }
+
+    #[test]
+    fn keeps_path_and_query() {
+        let res = call(
+            &mut HttpsRedirect::new(),
+            request("/some/path?a=1&b=2", Some("example.com")),
+        );
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(location(&res), "https://example.com/some/path?a=1&b=2");
+    }
+
+    #[test]
+    fn replaces_host_port() {
+        let res = call(
+            &mut HttpsRedirect::new().https_port(8443),
+            request("/", Some("example.com:8080")),
+        );
+        assert_eq!(location(&res), "https://example.com:8443/");
+
+        let res = call(
+            &mut HttpsRedirect::new(),
+            request("/", Some("example.com:8080")),
+        );
+        assert_eq!(location(&res), "https://example.com/");
+    }
+
+    #[test]
+    fn ipv6_host() {
+        let res = call(&mut HttpsRedirect::new(), request("/", Some("[::1]:80")));
+        assert_eq!(location(&res), "https://[::1]/");
+
+        let res = call(
+            &mut HttpsRedirect::new().https_port(8443),
+            request("/", Some("[::1]:80")),
+        );
+        assert_eq!(location(&res), "https://[::1]:8443/");
+    }
+
+    #[test]
+    fn missing_host_is_bad_request() {
+        let res = call(&mut HttpsRedirect::new(), request("/", None));
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.headers().get(header::LOCATION).is_none());
+    }
+
+    #[test]
+    fn pass_through_reaches_inner_service() {
+        let inner = hyper::service::service_fn(|_req: Request<Body>| {
+            ready(Ok::<_, Infallible>(Response::new(Body::from("inner"))))
+        });
+        let mut service = HttpsRedirect::new()
+            .pass_through("/.well-known/acme-challenge/")
+            .with_service(inner);
+
+        let res = call(
+            &mut service,
+            request("/.well-known/acme-challenge/token", Some("example.com")),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Only the prefix is passed through
+        let res = call(
+            &mut service,
+            request("/.well-known/other", Some("example.com")),
+        );
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[test]
+    fn pass_through_without_service_is_not_found() {
+        let res = call(
+            &mut HttpsRedirect::new().pass_through("/public/"),
+            request("/public/file", Some("example.com")),
+        );
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
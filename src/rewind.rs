@@ -0,0 +1,77 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A stream that gives back a byte that was already read from it before reading any more
+///
+/// Used to look at the first byte of a connection without needing the stream to support peeking
+#[derive(Debug)]
+pub struct Rewind<IO> {
+    prefix: Option<u8>,
+    inner: IO,
+}
+
+impl<IO> Rewind<IO> {
+    pub const fn new(inner: IO) -> Self {
+        Self {
+            prefix: None,
+            inner,
+        }
+    }
+
+    pub const fn with_prefix(inner: IO, prefix: u8) -> Self {
+        Self {
+            prefix: Some(prefix),
+            inner,
+        }
+    }
+
+    // The byte that will be given back, if it hasn't been read yet
+    pub const fn prefix(&self) -> Option<u8> {
+        self.prefix
+    }
+
+    pub const fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for Rewind<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if buf.remaining() > 0 {
+            if let Some(prefix) = self.prefix.take() {
+                buf.put_slice(&[prefix]);
+                return Poll::Ready(Ok(()));
+            }
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for Rewind<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
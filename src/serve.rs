@@ -0,0 +1,113 @@
+use hyper1::body::{Body, Incoming};
+use hyper1::service::Service;
+use hyper1::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+
+use crate::accept::{AcceptorError, HyperHttpOrHttpsAcceptor};
+use crate::conn::HttpOrHttpsConnection;
+use crate::listener::Listener;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl<L: Listener> HyperHttpOrHttpsAcceptor<L> {
+    /// Serve every connection with hyper 1 until the acceptor is shut down
+    ///
+    /// `make_service` is called with each new connection to create the service that handles its requests. Each
+    /// connection is served on its own task, using HTTP/1 or HTTP/2 depending on what the client speaks.
+    ///
+    /// Once the acceptor is shut down with its [`ShutdownHandle`](crate::ShutdownHandle), connections that are
+    /// still open are shut down gracefully (finishing the requests in progress), and this returns once they've all
+    /// closed. Errors from individual connections are ignored, but errors yielded by the acceptor end the loop straight
+    /// away, see [`Self::with_error_policy`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use flexible_hyper_server_tls::*;
+    /// use http_body_util::Full;
+    /// use hyper1::body::{Bytes, Incoming};
+    /// use hyper1::service::service_fn;
+    /// use hyper1::{Request, Response};
+    /// use std::convert::Infallible;
+    /// use tokio::net::TcpListener;
+    ///
+    /// async fn hello_world(_req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    ///     Ok(Response::new(Full::new(Bytes::from("Hello, World"))))
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
+    ///     let acceptor = HyperHttpOrHttpsAcceptor::new_http(listener);
+    ///
+    ///     acceptor
+    ///         .serve(|conn: &HttpOrHttpsConnection| {
+    ///             println!("Remote address: {}", conn.remote_addr());
+    ///             service_fn(hello_world)
+    ///         })
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// Errors if the acceptor yields an error.
+    pub async fn serve<F, S, B>(self, make_service: F) -> Result<(), AcceptorError>
+    where
+        F: FnMut(&HttpOrHttpsConnection<L::Io>) -> S,
+        S: Service<Request<Incoming>, Response = Response<B>> + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError>,
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        self.serve_with(Builder::new(TokioExecutor::new()), make_service)
+            .await
+    }
+
+    /// Serve every connection with hyper 1 until the acceptor is shut down, using a configured `Builder`
+    ///
+    /// Useful for setting HTTP/1 or HTTP/2 options, or only allowing one of them. See [`Self::serve`].
+    ///
+    /// # Errors
+    /// Errors if the acceptor yields an error.
+    pub async fn serve_with<F, S, B>(
+        mut self,
+        builder: Builder<TokioExecutor>,
+        mut make_service: F,
+    ) -> Result<(), AcceptorError>
+    where
+        F: FnMut(&HttpOrHttpsConnection<L::Io>) -> S,
+        S: Service<Request<Incoming>, Response = Response<B>> + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError>,
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let graceful = GracefulShutdown::new();
+
+        while let Some(conn) = self.accept().await {
+            let conn = conn?;
+            let service = make_service(&conn);
+            #[cfg(feature = "tracing")]
+            let span = conn.span().clone();
+            let conn = builder
+                .serve_connection_with_upgrades(TokioIo::new(conn), service)
+                .into_owned();
+            let conn = graceful.watch(conn);
+            let conn = async move {
+                // Like hyper's own server, errors from a single connection (like the client going away) are ignored
+                let _ = conn.await;
+            };
+            #[cfg(feature = "tracing")]
+            let conn = tracing::Instrument::instrument(conn, span);
+            tokio::spawn(conn);
+        }
+
+        graceful.shutdown().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use futures_util::task::AtomicWaker;
+use std::sync::{Arc, OnceLock};
+use std::task::Context;
+use std::time::Duration;
+
+/// A handle to shut down a `HyperHttpOrHttpsAcceptor` while it's being used by a hyper `Server`
+///
+/// Get one with `HyperHttpOrHttpsAcceptor::shutdown_handle`. Once shut down, the acceptor stops accepting new
+/// connections and then ends, which makes the `Server` resolve. Connections that have already been yielded aren't
+/// affected, use hyper's `with_graceful_shutdown` to wait for those.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    // Set once shutdown has been requested, holding how long handshakes in progress get to finish
+    grace_period: OnceLock<Duration>,
+    waker: AtomicWaker,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        Self {
+            shared: Arc::default(),
+        }
+    }
+
+    /// Stop accepting new connections, dropping any TLS handshakes that are still in progress
+    pub fn shutdown(&self) {
+        self.shutdown_gracefully(Duration::ZERO);
+    }
+
+    /// Stop accepting new connections, giving TLS handshakes that are still in progress up to `grace_period` to finish
+    ///
+    /// Handshakes that finish in time are still yielded by the acceptor. Calling this again after the acceptor has
+    /// started shutting down has no effect.
+    pub fn shutdown_gracefully(&self, grace_period: Duration) {
+        // Only the first request counts
+        let _ = self.shared.grace_period.set(grace_period);
+        self.shared.waker.wake();
+    }
+
+    // Check whether shutdown has been requested, making sure the acceptor is woken up if it is later
+    pub(crate) fn poll_requested(&self, cx: &Context<'_>) -> Option<Duration> {
+        self.shared.waker.register(cx.waker());
+        self.shared.grace_period.get().copied()
+    }
+}
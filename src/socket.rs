@@ -0,0 +1,154 @@
+use socket2::{SockRef, TcpKeepalive};
+use std::time::Duration;
+
+/// Options set on every TCP stream as soon as it's accepted, before the TLS handshake
+///
+/// Set them with `HyperHttpOrHttpsAcceptor::with_tcp_socket_options`. Options that aren't set are left as the OS
+/// default (or whatever the stream inherited from the listener).
+#[derive(Debug, Clone, Default)]
+pub struct TcpSocketOptions {
+    nodelay: Option<bool>,
+    keepalive_time: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    user_timeout: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl TcpSocketOptions {
+    /// Create a set of options that doesn't change anything
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            nodelay: None,
+            keepalive_time: None,
+            keepalive_interval: None,
+            keepalive_retries: None,
+            user_timeout: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+
+    /// Set `TCP_NODELAY`, which sends small writes straight away instead of waiting to combine them (Nagle's algorithm)
+    #[must_use]
+    pub const fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable `SO_KEEPALIVE`, sending keepalive probes once the connection has been idle for `time`
+    #[must_use]
+    pub const fn keepalive(mut self, time: Duration) -> Self {
+        self.keepalive_time = Some(time);
+        self
+    }
+
+    /// Set the time between keepalive probes (`TCP_KEEPINTVL`)
+    ///
+    /// Only has an effect if keepalive is enabled with [`Self::keepalive`]
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "illumos",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    #[must_use]
+    pub const fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set the number of unanswered keepalive probes before the connection is dropped (`TCP_KEEPCNT`)
+    ///
+    /// Only has an effect if keepalive is enabled with [`Self::keepalive`]
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "illumos",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    #[must_use]
+    pub const fn keepalive_retries(mut self, retries: u32) -> Self {
+        self.keepalive_retries = Some(retries);
+        self
+    }
+
+    /// Set `TCP_USER_TIMEOUT`, how long sent data can go unacknowledged before the connection is dropped
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    #[must_use]
+    pub const fn user_timeout(mut self, timeout: Duration) -> Self {
+        self.user_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the size of the send buffer (`SO_SNDBUF`)
+    #[must_use]
+    pub const fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the receive buffer (`SO_RCVBUF`)
+    #[must_use]
+    pub const fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    pub(crate) fn apply(&self, stream: &tokio::net::TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(time) = self.keepalive_time {
+            #[allow(unused_mut)]
+            let mut keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(any(
+                target_os = "android",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "fuchsia",
+                target_os = "illumos",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "netbsd",
+                target_os = "windows",
+            ))]
+            {
+                if let Some(interval) = self.keepalive_interval {
+                    keepalive = keepalive.with_interval(interval);
+                }
+                if let Some(retries) = self.keepalive_retries {
+                    keepalive = keepalive.with_retries(retries);
+                }
+            }
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        if let Some(timeout) = self.user_timeout {
+            socket.set_tcp_user_timeout(Some(timeout))?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
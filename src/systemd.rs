@@ -0,0 +1,106 @@
+use socket2::{Socket, Type};
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+use crate::listener::AnyListener;
+
+/// Error when taking the sockets passed in by systemd socket activation
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum SystemdError {
+    /// No sockets were passed to this process, because `LISTEN_FDS` isn't set or `LISTEN_PID` is for another process
+    #[error("no sockets were passed in by systemd")]
+    NoSockets,
+    /// `LISTEN_FDS` or `LISTEN_FDNAMES` couldn't be understood
+    #[error("invalid socket activation environment variables")]
+    InvalidEnv,
+    /// The sockets have already been taken, which can only be done once per process
+    #[error("sockets passed in by systemd have already been taken")]
+    AlreadyTaken,
+    /// A socket is not a stream socket that's listening for connections
+    #[error("socket {0:?} is not a listening stream socket")]
+    NotListening(String),
+    /// A socket's name doesn't say whether it's for HTTP or HTTPS
+    #[error("socket name {0:?} is not one of \"http\", \"https\" or \"http-and-https\"")]
+    UnknownName(String),
+    /// A socket is for HTTPS, but no `TlsAcceptor` was given to encrypt connections with
+    #[error("socket {0:?} is for HTTPS, but no TLS acceptor was given")]
+    MissingTlsAcceptor(String),
+    /// Checking or setting up a socket failed
+    #[error("failed to set up socket {name:?}")]
+    Io {
+        /// The name of the socket
+        name: String,
+        /// The error from the OS
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+// The first file descriptor passed in by systemd, after stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+// Set once the sockets have been taken, so that they can't end up with more than one owner
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+// Take ownership of the sockets passed in by systemd, along with their names
+pub fn take_listeners() -> Result<Vec<(String, AnyListener)>, SystemdError> {
+    let listen_pid = std::env::var("LISTEN_PID").map_err(|_| SystemdError::NoSockets)?;
+    if listen_pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Err(SystemdError::NoSockets);
+    }
+    let count: usize = std::env::var("LISTEN_FDS")
+        .map_err(|_| SystemdError::NoSockets)?
+        .parse()
+        .map_err(|_| SystemdError::InvalidEnv)?;
+    if count == 0 {
+        return Err(SystemdError::NoSockets);
+    }
+    let end = RawFd::try_from(count)
+        .ok()
+        .and_then(|count| LISTEN_FDS_START.checked_add(count))
+        .ok_or(SystemdError::InvalidEnv)?;
+    // Only older versions of systemd don't set names, so use the name newer versions give unnamed sockets
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES").map_or_else(
+        |_| vec![String::from("unknown"); count],
+        |names| names.split(':').map(String::from).collect(),
+    );
+    if names.len() != count {
+        return Err(SystemdError::InvalidEnv);
+    }
+    if TAKEN.swap(true, Ordering::AcqRel) {
+        return Err(SystemdError::AlreadyTaken);
+    }
+
+    let sockets: Vec<_> = (LISTEN_FDS_START..end)
+        // SAFETY: systemd passes these file descriptors to this process for it to own, and they can only be taken once
+        .map(|fd| unsafe { Socket::from_raw_fd(fd) })
+        .collect();
+    names
+        .into_iter()
+        .zip(sockets)
+        .map(|(name, socket)| match into_listener(socket) {
+            Ok(Some(listener)) => Ok((name, listener)),
+            Ok(None) => Err(SystemdError::NotListening(name)),
+            Err(source) => Err(SystemdError::Io { name, source }),
+        })
+        .collect()
+}
+
+// Check that the socket is listening for connections and convert it, or return `None` if it isn't
+fn into_listener(socket: Socket) -> std::io::Result<Option<AnyListener>> {
+    if socket.r#type()? != Type::STREAM || !socket.is_listener()? {
+        return Ok(None);
+    }
+    // Sockets passed in by systemd aren't closed on exec, unlike the ones we create ourselves
+    socket.set_cloexec(true)?;
+    socket.set_nonblocking(true)?;
+
+    let listener = if socket.local_addr()?.is_unix() {
+        AnyListener::Unix(tokio::net::UnixListener::from_std(socket.into())?)
+    } else {
+        AnyListener::Tcp(tokio::net::TcpListener::from_std(socket.into())?)
+    };
+    Ok(Some(listener))
+}
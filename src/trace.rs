@@ -0,0 +1,68 @@
+use tokio_rustls::rustls::ServerConnection;
+use tracing::field::{debug, display, Empty};
+
+use crate::event::{AcceptFailure, HandshakeFailure};
+use crate::listener::PeerAddr;
+use crate::metrics::ListenerMode;
+
+// The span covering a connection from when it's accepted until it's dropped
+pub fn connection_span(
+    peer_addr: &PeerAddr,
+    listener_index: usize,
+    mode: ListenerMode,
+) -> tracing::Span {
+    let span = tracing::info_span!(
+        "connection",
+        remote_addr = Empty,
+        listener_index,
+        mode = ?mode,
+    );
+    if let Some(addr) = peer_addr.tcp() {
+        span.record("remote_addr", display(addr));
+    } else {
+        span.record("remote_addr", debug(peer_addr));
+    }
+    span
+}
+
+// The span covering the handshake, with the details of the TLS session filled in once it's done
+pub fn handshake_span(connection_span: &tracing::Span) -> tracing::Span {
+    tracing::debug_span!(
+        parent: connection_span,
+        "handshake",
+        tls.version = Empty,
+        tls.cipher_suite = Empty,
+        tls.alpn = Empty,
+        tls.sni = Empty,
+    )
+}
+
+// Record what was negotiated in the current handshake span
+pub fn record_tls(session: &ServerConnection) {
+    let span = tracing::Span::current();
+    if let Some(version) = session.protocol_version() {
+        span.record("tls.version", debug(version));
+    }
+    if let Some(suite) = session.negotiated_cipher_suite() {
+        span.record("tls.cipher_suite", debug(suite.suite()));
+    }
+    if let Some(alpn) = session.alpn_protocol() {
+        span.record("tls.alpn", display(String::from_utf8_lossy(alpn)));
+    }
+    if let Some(sni) = session.server_name() {
+        span.record("tls.sni", sni);
+    }
+}
+
+pub fn handshake_failed(failure: &HandshakeFailure) {
+    tracing::debug!(kind = ?failure.kind, elapsed = ?failure.elapsed, "handshake failed");
+}
+
+pub fn accept_failed(failure: &AcceptFailure) {
+    tracing::warn!(
+        listener_index = failure.listener_index,
+        error = %failure.error,
+        retry_in = ?failure.retry_in,
+        "failed to accept connection",
+    );
+}
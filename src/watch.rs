@@ -0,0 +1,276 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::{self, ClientConfig, ServerName};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::mode::TlsHandle;
+use crate::tlsconfig::{get_tlsacceptor_from_files, HttpProtocol, TlsAcceptorError};
+
+/// Watches a certificate and key file, loading them into running listeners whenever they change
+///
+/// The files are checked every poll interval, which works the same whether they're written in place, replaced by
+/// renaming a new file over them (like editors and certbot do), or swapped through a symlink (like Kubernetes secret
+/// mounts do). Once a change is seen, the files are loaded after they've stopped changing for the debounce time, so
+/// that a certificate and key written one after the other are loaded together.
+///
+/// The new certificate and key are loaded with [`get_tlsacceptor_from_files`] and checked with a test handshake
+/// before being given to the listeners. If either step fails, the listeners keep using their current certificate,
+/// and the files are tried again the next time they change.
+///
+/// ## Example
+/// ```no_run
+/// # use flexible_hyper_server_tls::*;
+/// # async fn run(acceptor: HyperHttpOrHttpsAcceptor) {
+/// let watcher = CertWatcher::new(
+///     "./cert.cer",
+///     "./key.pem",
+///     tlsconfig::HttpProtocol::Both,
+///     acceptor.tls_handle(0).unwrap(),
+/// )
+/// .with_event_handler(|event| println!("{event:?}"));
+/// tokio::spawn(watcher.run());
+/// # }
+/// ```
+pub struct CertWatcher {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    protocol: HttpProtocol,
+    tls_handles: Vec<TlsHandle>,
+    poll_interval: Duration,
+    debounce: Duration,
+    event_handler: Option<CertReloadEventHandler>,
+}
+
+type CertReloadEventHandler = Box<dyn Fn(CertReloadEvent<'_>) + Send + Sync>;
+
+/// Something that happened while watching certificate files, reported to the handler set with
+/// `CertWatcher::with_event_handler`
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum CertReloadEvent<'a> {
+    /// The files changed, and the new certificate was given to the listeners
+    Reloaded,
+    /// The files changed, but the new certificate couldn't be used, so the listeners kept the one they had
+    Failed(&'a CertReloadError),
+}
+
+/// Error when loading changed certificate files
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum CertReloadError {
+    /// The files couldn't be read, or didn't contain a valid certificate and key
+    #[error("failed to load certificate and key")]
+    Load(#[from] TlsAcceptorError),
+    /// A test handshake with the new certificate failed, usually because the key doesn't match the certificate
+    #[error("test handshake with new certificate failed")]
+    Check(#[source] std::io::Error),
+}
+
+impl CertWatcher {
+    /// Create a watcher that loads the files at `cert_path` and `key_path` into the listener of `tls_handle`
+    ///
+    /// `protocol` is used to create the new `TlsAcceptor` each time, see [`get_tlsacceptor_from_files`].
+    pub fn new(
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+        protocol: HttpProtocol,
+        tls_handle: TlsHandle,
+    ) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            protocol,
+            tls_handles: vec![tls_handle],
+            poll_interval: Duration::from_secs(5),
+            debounce: Duration::from_secs(1),
+            event_handler: None,
+        }
+    }
+
+    /// Also load the files into the listener of another `TlsHandle`
+    ///
+    /// Listeners that only accept HTTP when the files change are skipped.
+    #[must_use]
+    pub fn with_tls_handle(mut self, tls_handle: TlsHandle) -> Self {
+        self.tls_handles.push(tls_handle);
+        self
+    }
+
+    /// Set how often to check whether the files have changed (5 seconds by default)
+    #[must_use]
+    pub const fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set how long the files have to stop changing for before they're loaded (1 second by default)
+    #[must_use]
+    pub const fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Set a function to be called whenever the files change, with whether they were loaded
+    #[must_use]
+    pub fn with_event_handler(
+        mut self,
+        handler: impl Fn(CertReloadEvent<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Watch the files until the returned future is dropped
+    ///
+    /// The files are assumed to be the ones the listeners were started with, so nothing is loaded until they change.
+    /// Spawn this onto its own task, and abort the task to stop watching.
+    pub async fn run(self) {
+        let mut loaded = self.files_state().await;
+        loop {
+            tokio::time::sleep(self.poll_interval).await;
+            let mut current = self.files_state().await;
+            if current == loaded {
+                continue;
+            }
+            loop {
+                tokio::time::sleep(self.debounce).await;
+                let next = self.files_state().await;
+                if next == current {
+                    break;
+                }
+                current = next;
+            }
+            loaded = current;
+
+            match self.load().await {
+                Ok(tls_acceptor) => {
+                    for tls_handle in &self.tls_handles {
+                        // Listeners that only accept HTTP don't need it
+                        let _ = tls_handle.set(tls_acceptor.clone());
+                    }
+                    self.report(CertReloadEvent::Reloaded);
+                }
+                Err(err) => self.report(CertReloadEvent::Failed(&err)),
+            }
+        }
+    }
+
+    async fn load(&self) -> Result<TlsAcceptor, CertReloadError> {
+        let (cert_path, key_path, protocol) =
+            (self.cert_path.clone(), self.key_path.clone(), self.protocol);
+        let tls_acceptor =
+            blocking(move || get_tlsacceptor_from_files(cert_path, key_path, protocol))
+                .await
+                .map_err(|err| CertReloadError::Load(TlsAcceptorError::Io(err)))??;
+        check(&tls_acceptor).await.map_err(CertReloadError::Check)?;
+        Ok(tls_acceptor)
+    }
+
+    async fn files_state(&self) -> (Option<FileState>, Option<FileState>) {
+        let (cert_path, key_path) = (self.cert_path.clone(), self.key_path.clone());
+        // Treated as missing if the task couldn't be run, so they're checked again next time
+        blocking(move || (FileState::read(&cert_path), FileState::read(&key_path)))
+            .await
+            .unwrap_or((None, None))
+    }
+
+    fn report(&self, event: CertReloadEvent<'_>) {
+        #[cfg(feature = "tracing")]
+        match event {
+            CertReloadEvent::Reloaded => {
+                tracing::info!(cert_path = ?self.cert_path, "reloaded certificate");
+            }
+            CertReloadEvent::Failed(err) => {
+                tracing::warn!(cert_path = ?self.cert_path, error = %err, "failed to reload certificate");
+            }
+        }
+        if let Some(handler) = &self.event_handler {
+            handler(event);
+        }
+    }
+}
+
+impl std::fmt::Debug for CertWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertWatcher")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .field("poll_interval", &self.poll_interval)
+            .field("debounce", &self.debounce)
+            .finish_non_exhaustive()
+    }
+}
+
+// Run file system calls on the blocking thread pool, so that a slow file system can't hold up the runtime
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> std::io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::other)
+}
+
+// Enough about a file to tell when it's been changed, replaced, or swapped through a symlink, or `None` if it's
+// missing (like part way through being replaced)
+#[derive(Debug, PartialEq, Eq)]
+struct FileState {
+    target: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: (u64, u64),
+}
+
+impl FileState {
+    fn read(path: &Path) -> Option<Self> {
+        let target = std::fs::canonicalize(path).ok()?;
+        let metadata = std::fs::metadata(&target).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: {
+                use std::os::unix::fs::MetadataExt;
+                (metadata.dev(), metadata.ino())
+            },
+            target,
+        })
+    }
+}
+
+// rustls doesn't check that the key matches the certificate when it's loaded, so make sure it does by connecting to
+// the new acceptor in memory, which fails if the handshake isn't signed by the key in the certificate
+async fn check(tls_acceptor: &TlsAcceptor) -> std::io::Result<()> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+    let server_name = ServerName::IpAddress(std::net::Ipv4Addr::LOCALHOST.into());
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    futures_util::future::try_join(
+        connector.connect(server_name, client),
+        tls_acceptor.accept(server),
+    )
+    .await?;
+    Ok(())
+}
+
+// Only the signature made with the key is being checked, so any certificate (even one for another name) is accepted
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,123 @@
+//! Fixtures shared by the integration tests
+// Each test file only uses some of these
+#![allow(dead_code)]
+
+use flexible_hyper_server_tls::{tlsconfig, Listener, PeerAddr};
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::{self, ClientConfig, ServerName};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub const CERT: &str = "examples/certs/cert.pem";
+pub const KEY: &str = "examples/certs/key.pem";
+
+/// A listener that accepts in-memory connections
+pub enum TestListener {
+    /// Always has another connection waiting, which the client closes straight away, like a SYN flood would
+    Flood,
+    /// Connections made with the matching [`Clients`]
+    Clients(mpsc::UnboundedReceiver<(DuplexStream, PeerAddr)>),
+}
+
+/// Connects to a [`TestListener`]
+#[derive(Clone)]
+pub struct Clients(mpsc::UnboundedSender<(DuplexStream, PeerAddr)>);
+
+impl TestListener {
+    pub fn new() -> (Self, Clients) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self::Clients(receiver), Clients(sender))
+    }
+}
+
+impl Listener for TestListener {
+    type Io = DuplexStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<(Self::Io, PeerAddr)>> {
+        match self {
+            Self::Flood => {
+                let (_client, server) = tokio::io::duplex(64);
+                Poll::Ready(Ok((server, PeerAddr::Unknown)))
+            }
+            Self::Clients(receiver) => match receiver.poll_recv(cx) {
+                Poll::Ready(Some(conn)) => Poll::Ready(Ok(conn)),
+                _ => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl Clients {
+    /// Connect, returning the client's end of the stream
+    pub fn connect(&self) -> DuplexStream {
+        self.connect_as(PeerAddr::Unknown)
+    }
+
+    /// Connect from a made up TCP address
+    pub fn connect_from(&self, addr: &str) -> DuplexStream {
+        self.connect_as(PeerAddr::Tcp(addr.parse().unwrap()))
+    }
+
+    fn connect_as(&self, peer_addr: PeerAddr) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        self.0.send((server, peer_addr)).unwrap();
+        client
+    }
+}
+
+/// The `TlsAcceptor` for the example certificate
+pub fn tls_acceptor() -> TlsAcceptor {
+    tlsconfig::get_tlsacceptor_from_files(CERT, KEY, tlsconfig::HttpProtocol::Http1).unwrap()
+}
+
+/// A connector that accepts any certificate, since the test certificates are self-signed
+pub fn tls_connector() -> TlsConnector {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// The name to connect to with [`tls_connector`]
+pub fn server_name() -> ServerName {
+    ServerName::IpAddress(std::net::Ipv4Addr::LOCALHOST.into())
+}
+
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Fail the test if `future` takes longer than 2 seconds
+pub async fn within<T>(future: impl Future<Output = T>) -> T {
+    tokio::time::timeout(Duration::from_secs(2), future)
+        .await
+        .expect("timed out")
+}
+
+/// Wait until `check` is true, for things happening on other tasks
+pub async fn wait_until(check: impl Fn() -> bool) {
+    within(async {
+        while !check() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
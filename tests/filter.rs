@@ -0,0 +1,32 @@
+//! Checks that changing the IP filter of a running acceptor applies to the next connection it accepts
+
+mod common;
+
+use common::TestListener;
+use flexible_hyper_server_tls::{HyperHttpOrHttpsAcceptor, IpFilter};
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn filter_handle_applies_to_next_accept() {
+    let (listener, clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http(listener)
+        .with_ip_filter(IpFilter::new().allow("10.0.0.0/8".parse().unwrap()));
+    let handle = acceptor.ip_filter_handle();
+
+    let _client = clients.connect_from("10.0.0.1:1000");
+    let mut denied = clients.connect_from("192.0.2.1:1000");
+    let _client = clients.connect_from("10.0.0.2:1000");
+    let conn = acceptor.accept().await.unwrap().unwrap();
+    assert_eq!(conn.remote_addr(), "10.0.0.1:1000".parse().unwrap());
+    // The denied client was skipped, and its connection closed
+    let conn = acceptor.accept().await.unwrap().unwrap();
+    assert_eq!(conn.remote_addr(), "10.0.0.2:1000".parse().unwrap());
+    assert_eq!(denied.read(&mut [0; 1]).await.unwrap(), 0);
+
+    handle.set(IpFilter::new().deny("10.0.0.1".parse().unwrap()));
+    let mut denied = clients.connect_from("10.0.0.1:1001");
+    let _client = clients.connect_from("192.0.2.1:1001");
+    let conn = acceptor.accept().await.unwrap().unwrap();
+    assert_eq!(conn.remote_addr(), "192.0.2.1:1001".parse().unwrap());
+    assert_eq!(denied.read(&mut [0; 1]).await.unwrap(), 0);
+}
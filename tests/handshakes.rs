@@ -0,0 +1,135 @@
+//! Checks how connections from HTTP listeners share the handshake queue with TLS handshakes
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, wait_until, within, TestListener};
+use flexible_hyper_server_tls::{
+    AcceptorEvent, HandshakeFailureKind, HttpOrHttpsConnection, HyperHttpOrHttpsAcceptor, Listener,
+    PeerAddr,
+};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+// A listener with a single client, whose connection is reset before it sends anything
+struct ResetListener(bool);
+
+struct ResetStream;
+
+impl Listener for ResetListener {
+    type Io = ResetStream;
+
+    fn poll_accept(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, PeerAddr)>> {
+        if std::mem::replace(&mut self.0, true) {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok((ResetStream, PeerAddr::Unknown)))
+        }
+    }
+}
+
+impl AsyncRead for ResetStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+}
+
+impl AsyncWrite for ResetStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn idle_http_clients_dont_block_https() {
+    let (http_listener, http_clients) = TestListener::new();
+    let (https_listener, https_clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http(http_listener)
+        .with_https(https_listener, tls_acceptor(), Duration::from_secs(10))
+        .with_max_handshakes(2)
+        .with_first_byte_timeout(Duration::from_secs(5));
+    let queue = acceptor.handshake_queue_handle();
+
+    let (conn_sender, mut conns) = mpsc::unbounded_channel::<HttpOrHttpsConnection<DuplexStream>>();
+    let server = tokio::spawn(async move {
+        while let Some(conn) = acceptor.accept().await {
+            conn_sender.send(conn.unwrap()).unwrap();
+        }
+    });
+
+    // Fill the room for HTTP clients waiting for their first byte, leaving the third in the backlog
+    let mut idle_clients: Vec<_> = (0..3).map(|_| http_clients.connect()).collect();
+    wait_until(|| queue.pending_http_waits() == 2).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(queue.pending_http_waits(), 2);
+    assert_eq!(queue.pending_handshakes(), 0);
+
+    // HTTPS is still accepted
+    let client = tokio::spawn(tls_connector().connect(server_name(), https_clients.connect()));
+    let conn = within(conns.recv()).await.unwrap();
+    assert!(conn.is_https());
+    assert_eq!(conn.listener_index(), 1);
+    let _tls_client = within(client).await.unwrap().unwrap();
+    assert_eq!(queue.pending_handshakes(), 0);
+
+    // Once an HTTP client sends something, the one in the backlog takes its place
+    idle_clients[0].write_all(b"G").await.unwrap();
+    let conn = within(conns.recv()).await.unwrap();
+    assert!(!conn.is_https());
+    assert_eq!(conn.listener_index(), 0);
+    wait_until(|| queue.pending_http_waits() == 2).await;
+    idle_clients[2].write_all(b"G").await.unwrap();
+    assert_eq!(within(conns.recv()).await.unwrap().listener_index(), 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn http_first_byte_errors_are_only_reported() {
+    let (sender, mut failures) = mpsc::unbounded_channel();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http(ResetListener(false))
+        .with_first_byte_timeout(Duration::from_secs(5))
+        .with_event_handler(move |event| {
+            if let AcceptorEvent::HandshakeFailed(failure) = event {
+                let _ = sender.send(matches!(failure.kind, HandshakeFailureKind::FirstByte(_)));
+            }
+        });
+
+    // Yielding the error would end hyper's server, so the acceptor just waits for the next connection
+    let res = tokio::time::timeout(Duration::from_millis(100), acceptor.accept()).await;
+    assert!(
+        res.is_err(),
+        "acceptor yielded {:?}",
+        res.map(|res| res.map(|res| res.err()))
+    );
+    assert!(within(failures.recv()).await.unwrap());
+}
+
+#[tokio::test]
+async fn plain_http_isnt_held_back_by_handshake_limit() {
+    // HTTP connections don't wait for anything without a first-byte timeout or PROXY protocol, so they aren't queued
+    let (listener, clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http(listener).with_max_handshakes(0);
+    let _client = clients.connect();
+    let conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(!conn.is_https());
+}
@@ -0,0 +1,78 @@
+//! Checks that TLS handshakes keep being delivered while the acceptor is flooded with new connections
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, Clients, TestListener};
+use flexible_hyper_server_tls::{ErrorPolicy, HyperHttpOrHttpsAcceptor};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::TlsConnector;
+
+const TLS_CLIENTS: usize = 50;
+
+// Make a TLS connection and wait for the byte the server sends once it has the connection
+async fn tls_client(clients: Clients, connector: TlsConnector) {
+    let client = clients.connect();
+    let mut stream = connector.connect(server_name(), client).await.unwrap();
+    assert_eq!(stream.read_u8().await.unwrap(), b'x');
+}
+
+async fn connection_storm(spawn_handshakes: bool) {
+    let tls_acceptor = tls_acceptor();
+    let (listener, clients) = TestListener::new();
+
+    // Flood both an HTTP and an HTTPS listener, so that there are always connections to yield and handshakes to fail
+    let acceptor = HyperHttpOrHttpsAcceptor::new_http(TestListener::Flood)
+        .with_https(
+            TestListener::Flood,
+            tls_acceptor.clone(),
+            Duration::from_secs(10),
+        )
+        .with_https(listener, tls_acceptor, Duration::from_secs(10))
+        .with_error_policy(ErrorPolicy::SkipTransient)
+        .with_accept_budget(16);
+    let mut acceptor = if spawn_handshakes {
+        acceptor.with_spawned_handshakes()
+    } else {
+        acceptor
+    };
+    let server = tokio::spawn(async move {
+        while let Some(conn) = acceptor.accept().await {
+            let Ok(mut conn) = conn else { continue };
+            if conn.is_https() {
+                tokio::spawn(async move {
+                    let _ = conn.write_all(b"x").await;
+                    let _ = conn.flush().await;
+                });
+            }
+        }
+    });
+
+    let connector = tls_connector();
+    let tasks: Vec<_> = (0..TLS_CLIENTS)
+        .map(|_| tokio::spawn(tls_client(clients.clone(), connector.clone())))
+        .collect();
+    let finished = tokio::time::timeout(Duration::from_secs(30), async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+    })
+    .await;
+
+    server.abort();
+    assert!(
+        finished.is_ok(),
+        "TLS clients didn't get their connections during the storm"
+    );
+}
+
+// Everything runs on one thread, so the clients only get anywhere if the acceptor lets other tasks run
+#[tokio::test(flavor = "current_thread")]
+async fn handshakes_are_delivered_during_connection_storm() {
+    connection_storm(false).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn spawned_handshakes_are_delivered_during_connection_storm() {
+    connection_storm(true).await;
+}
@@ -0,0 +1,87 @@
+//! Checks that metrics tell TLS handshakes apart from HTTP connections waiting for their first byte
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, within, TestListener};
+use flexible_hyper_server_tls::{AcceptorMetrics, HyperHttpOrHttpsAcceptor, ListenerMode};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Default)]
+struct Recorder {
+    started: Mutex<Vec<ListenerMode>>,
+    succeeded: Mutex<Vec<ListenerMode>>,
+    // The latest number of handshakes and HTTP waits
+    queue: Mutex<(usize, usize)>,
+}
+
+impl AcceptorMetrics for Recorder {
+    fn handshake_started(&self, mode: ListenerMode) {
+        self.started.lock().unwrap().push(mode);
+    }
+
+    fn handshake_succeeded(&self, mode: ListenerMode, _elapsed: Duration) {
+        self.succeeded.lock().unwrap().push(mode);
+    }
+
+    fn handshake_queue_len(&self, handshakes: usize, http_waits: usize) {
+        *self.queue.lock().unwrap() = (handshakes, http_waits);
+    }
+}
+
+// Lets the test look at what was recorded after giving the acceptor a reference to it
+struct Shared(Arc<Recorder>);
+
+impl AcceptorMetrics for Shared {
+    fn handshake_started(&self, mode: ListenerMode) {
+        self.0.handshake_started(mode);
+    }
+
+    fn handshake_succeeded(&self, mode: ListenerMode, elapsed: Duration) {
+        self.0.handshake_succeeded(mode, elapsed);
+    }
+
+    fn handshake_queue_len(&self, handshakes: usize, http_waits: usize) {
+        self.0.handshake_queue_len(handshakes, http_waits);
+    }
+}
+
+#[tokio::test]
+async fn http_waits_are_reported_separately() {
+    let recorder = Arc::new(Recorder::default());
+    let (http_listener, http_clients) = TestListener::new();
+    let (https_listener, https_clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http(http_listener)
+        .with_https(https_listener, tls_acceptor(), Duration::from_secs(10))
+        .with_first_byte_timeout(Duration::from_secs(5))
+        .with_metrics(Shared(recorder.clone()));
+    let queue = acceptor.handshake_queue_handle();
+    let queue_len = || *recorder.queue.lock().unwrap();
+
+    let mut http_client = http_clients.connect();
+    let tls_client = tokio::spawn(tls_connector().connect(server_name(), https_clients.connect()));
+    let conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(conn.is_https());
+    let _tls_client = within(tls_client).await.unwrap().unwrap();
+    assert_eq!(
+        *recorder.started.lock().unwrap(),
+        [ListenerMode::Http, ListenerMode::Https]
+    );
+    assert_eq!(*recorder.succeeded.lock().unwrap(), [ListenerMode::Https]);
+    // The HTTP client is still waiting to send something
+    assert_eq!(queue_len(), (0, 1));
+    assert_eq!(
+        queue_len(),
+        (queue.pending_handshakes(), queue.pending_http_waits())
+    );
+
+    http_client.write_all(b"G").await.unwrap();
+    let conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(!conn.is_https());
+    assert_eq!(
+        *recorder.succeeded.lock().unwrap(),
+        [ListenerMode::Https, ListenerMode::Http]
+    );
+    assert_eq!(queue_len(), (0, 0));
+}
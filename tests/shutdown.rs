@@ -0,0 +1,65 @@
+//! Checks that TLS handshakes in progress when the acceptor is shut down get the grace period to finish, and no longer
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, wait_until, within, TestListener};
+use flexible_hyper_server_tls::HyperHttpOrHttpsAcceptor;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+const GRACE_PERIOD: Duration = Duration::from_millis(300);
+
+async fn handshakes_finish_within_grace_period(spawn_handshakes: bool) {
+    let (listener, clients) = TestListener::new();
+    let acceptor =
+        HyperHttpOrHttpsAcceptor::new_https(listener, tls_acceptor(), Duration::from_secs(10));
+    let mut acceptor = if spawn_handshakes {
+        acceptor.with_spawned_handshakes()
+    } else {
+        acceptor
+    };
+    let queue = acceptor.handshake_queue_handle();
+    let shutdown = acceptor.shutdown_handle();
+
+    let (conn_sender, mut conns) = mpsc::unbounded_channel();
+    let server = tokio::spawn(async move {
+        while let Some(conn) = acceptor.accept().await {
+            conn_sender.send(conn.unwrap()).unwrap();
+        }
+    });
+
+    // Neither client has started its handshake yet
+    let on_time = clients.connect();
+    let mut late = clients.connect();
+    wait_until(|| queue.pending_handshakes() == 2).await;
+    shutdown.shutdown_gracefully(GRACE_PERIOD);
+
+    // A handshake finished during the grace period is still yielded
+    let client = tokio::spawn(tls_connector().connect(server_name(), on_time));
+    let conn = within(conns.recv()).await.unwrap();
+    assert!(conn.is_https());
+    let _tls_client = within(client).await.unwrap().unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), late.read(&mut [0; 1]))
+            .await
+            .is_err(),
+        "handshake dropped before the grace period ran out"
+    );
+
+    // The other is dropped once it's over, and then the acceptor ends
+    within(server).await.unwrap();
+    assert_eq!(within(late.read(&mut [0; 1])).await.unwrap(), 0);
+    assert!(conns.recv().await.is_none());
+    assert_eq!(queue.pending_handshakes(), 0);
+}
+
+#[tokio::test]
+async fn handshakes_in_progress_get_grace_period() {
+    handshakes_finish_within_grace_period(false).await;
+}
+
+#[tokio::test]
+async fn spawned_handshakes_in_progress_get_grace_period() {
+    handshakes_finish_within_grace_period(true).await;
+}
@@ -0,0 +1,68 @@
+//! Checks that a listener accepting both HTTP and HTTPS tells them apart by the first byte without losing it
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, within, TestListener};
+use flexible_hyper_server_tls::HyperHttpOrHttpsAcceptor;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+#[tokio::test]
+async fn plain_http_keeps_first_byte() {
+    let (listener, clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http_and_https(
+        listener,
+        tls_acceptor(),
+        Duration::from_secs(10),
+    );
+
+    let mut client = clients.connect();
+    client.write_all(REQUEST).await.unwrap();
+    let mut conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(!conn.is_https());
+
+    let mut request = vec![0; REQUEST.len()];
+    conn.read_exact(&mut request).await.unwrap();
+    assert_eq!(request, REQUEST);
+}
+
+#[tokio::test]
+async fn client_hello_gets_tls() {
+    let (listener, clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http_and_https(
+        listener,
+        tls_acceptor(),
+        Duration::from_secs(10),
+    );
+
+    // Every TLS connection starts with a handshake record, 0x16
+    let client = tokio::spawn(tls_connector().connect(server_name(), clients.connect()));
+    let mut conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(conn.is_https());
+
+    let mut client = within(client).await.unwrap().unwrap();
+    client.write_all(REQUEST).await.unwrap();
+    client.flush().await.unwrap();
+    let mut request = vec![0; REQUEST.len()];
+    within(conn.read_exact(&mut request)).await.unwrap();
+    assert_eq!(request, REQUEST);
+}
+
+#[tokio::test]
+async fn eof_first_is_http() {
+    let (listener, clients) = TestListener::new();
+    let mut acceptor = HyperHttpOrHttpsAcceptor::new_http_and_https(
+        listener,
+        tls_acceptor(),
+        Duration::from_secs(10),
+    );
+
+    // The client closes its side without sending anything
+    let mut client = clients.connect();
+    client.shutdown().await.unwrap();
+    let mut conn = within(acceptor.accept()).await.unwrap().unwrap();
+    assert!(!conn.is_https());
+    assert_eq!(conn.read(&mut [0; 1]).await.unwrap(), 0);
+}
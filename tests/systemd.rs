@@ -0,0 +1,166 @@
+//! Checks taking sockets passed in by systemd, by running this test binary again with the sockets in place the same
+//! way systemd would pass them
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::tls_acceptor;
+use flexible_hyper_server_tls::{HyperHttpOrHttpsAcceptor, ListenerMode, SystemdError};
+use socket2::{Domain, Socket, Type};
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+
+// Set in the child process to the case it should check
+const CASE_VAR: &str = "SYSTEMD_TEST_CASE";
+
+fn listening_socket() -> Socket {
+    let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    socket
+        .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+        .unwrap();
+    socket.listen(16).unwrap();
+    socket
+}
+
+// Run the `child` test in a new process, with `sockets` passed in from fd 3 onwards under `names`
+fn run_child(case: &str, sockets: &[&Socket], names: &str) {
+    let fds: Vec<RawFd> = sockets.iter().map(|socket| socket.as_raw_fd()).collect();
+    let mut command = Command::new("sh");
+    // The shell gives the child its own PID in LISTEN_PID before replacing itself with it, like systemd does
+    command
+        .args(["-c", "LISTEN_PID=$$ exec \"$0\" \"$@\""])
+        .arg(std::env::current_exe().unwrap())
+        .args(["child", "--exact", "--nocapture", "--test-threads=1"])
+        .env(CASE_VAR, case)
+        .env("LISTEN_FDS", fds.len().to_string())
+        .env("LISTEN_FDNAMES", names);
+    // SAFETY: only async-signal-safe functions are called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // Move every socket out of the way first, so that none of them is overwritten before it's been moved
+            // into place
+            let mut moved = Vec::with_capacity(fds.len());
+            for &fd in &fds {
+                let new_fd = libc::fcntl(fd, libc::F_DUPFD, 100);
+                if new_fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                moved.push(new_fd);
+            }
+            // Duplicated file descriptors are left open on exec
+            for (target, fd) in (3..).zip(moved) {
+                if libc::dup2(fd, target) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let status = command.status().unwrap();
+    assert!(status.success(), "child process for {case:?} failed");
+}
+
+// Does nothing unless run by one of the other tests
+#[tokio::test]
+async fn child() {
+    let Ok(case) = std::env::var(CASE_VAR) else {
+        return;
+    };
+    let tls_acceptor = (case != "missing_tls_acceptor").then(tls_acceptor);
+    let res =
+        HyperHttpOrHttpsAcceptor::from_systemd(tls_acceptor.as_ref(), Duration::from_secs(10));
+    match case.as_str() {
+        "listeners" => {
+            let mut acceptor = res.unwrap();
+            let modes: Vec<_> = (0..3)
+                .map(|i| acceptor.mode_handle(i).unwrap().mode())
+                .collect();
+            assert_eq!(
+                modes,
+                [
+                    ListenerMode::Http,
+                    ListenerMode::Https,
+                    ListenerMode::HttpAndHttps
+                ]
+            );
+            assert!(acceptor.mode_handle(3).is_none());
+
+            // The parent connected to the HTTP socket before starting us
+            let conn = acceptor.accept().await.unwrap().unwrap();
+            assert_eq!(conn.listener_index(), 0);
+            assert!(!conn.is_https());
+
+            // The sockets can only be taken once
+            assert!(matches!(
+                HyperHttpOrHttpsAcceptor::from_systemd(
+                    tls_acceptor.as_ref(),
+                    Duration::from_secs(10)
+                ),
+                Err(SystemdError::AlreadyTaken)
+            ));
+        }
+        "unknown_name" => {
+            assert!(matches!(res, Err(SystemdError::UnknownName(name)) if name == "gopher"));
+        }
+        "not_listening" => {
+            assert!(matches!(res, Err(SystemdError::NotListening(name)) if name == "https"));
+        }
+        "missing_tls_acceptor" => {
+            assert!(matches!(res, Err(SystemdError::MissingTlsAcceptor(name)) if name == "https"));
+        }
+        _ => panic!("unknown case {case:?}"),
+    }
+}
+
+#[test]
+fn http_and_https_listeners() {
+    let http = listening_socket();
+    let https = listening_socket();
+    let both = listening_socket();
+    // Waits in the backlog until the child accepts it
+    let _client = TcpStream::connect(http.local_addr().unwrap().as_socket().unwrap()).unwrap();
+    run_child(
+        "listeners",
+        &[&http, &https, &both],
+        "http:https:http-and-https",
+    );
+}
+
+#[test]
+fn unknown_name() {
+    let http = listening_socket();
+    let other = listening_socket();
+    run_child("unknown_name", &[&http, &other], "http:gopher");
+}
+
+#[test]
+fn not_listening() {
+    let http = listening_socket();
+    // Bound, but never told to listen
+    let unlistened = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    unlistened
+        .bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into())
+        .unwrap();
+    run_child("not_listening", &[&http, &unlistened], "http:https");
+
+    let udp = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+    run_child("not_listening", &[&http, &udp], "http:https");
+}
+
+#[test]
+fn missing_tls_acceptor() {
+    let https = listening_socket();
+    run_child("missing_tls_acceptor", &[&https], "https");
+}
+
+#[tokio::test]
+async fn no_sockets() {
+    // This process wasn't given any, since LISTEN_PID isn't set to it
+    assert!(matches!(
+        HyperHttpOrHttpsAcceptor::from_systemd(None, Duration::from_secs(10)),
+        Err(SystemdError::NoSockets)
+    ));
+}
@@ -0,0 +1,199 @@
+//! Checks that `CertWatcher` loads changed certificate files, however they're replaced, and keeps the old certificate
+//! when the new files can't be used
+#![cfg(feature = "watch")]
+
+mod common;
+
+use common::{server_name, tls_acceptor, tls_connector, CERT as OLD_CERT, KEY as OLD_KEY};
+use flexible_hyper_server_tls::{
+    tlsconfig, CertReloadEvent, CertWatcher, HyperHttpOrHttpsAcceptor, TlsHandle,
+};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// The certificate the listener starts with is the example one, and this is the one it's changed to
+const NEW_CERT: &str = "tests/certs/cert.pem";
+const NEW_KEY: &str = "tests/certs/key.pem";
+
+// Longer than it takes to write both files, so that they're always loaded together
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// A directory that's deleted when the test ends
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "flexible-hyper-server-tls-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// Whether a reload worked, or why it didn't
+type Events = mpsc::UnboundedReceiver<Result<(), String>>;
+
+// Start an HTTPS listener with the old certificate, and a watcher loading the files at `cert_path` and `key_path`
+// into it
+async fn start(cert_path: &Path, key_path: &Path) -> (TlsHandle, Events) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let acceptor =
+        HyperHttpOrHttpsAcceptor::new_https(listener, tls_acceptor(), Duration::from_secs(10));
+    let tls_handle = acceptor.tls_handle(0).unwrap();
+
+    let (sender, events) = mpsc::unbounded_channel();
+    let watcher = CertWatcher::new(
+        cert_path,
+        key_path,
+        tlsconfig::HttpProtocol::Http1,
+        tls_handle.clone(),
+    )
+    .with_poll_interval(Duration::from_millis(20))
+    .with_debounce(DEBOUNCE)
+    .with_event_handler(move |event| {
+        let _ = sender.send(match event {
+            CertReloadEvent::Reloaded => Ok(()),
+            CertReloadEvent::Failed(err) => Err(format!("{err:?}")),
+            _ => Err(String::from("unknown event")),
+        });
+    });
+    tokio::spawn(watcher.run());
+    // Give the watcher time to see the files as they are
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    (tls_handle, events)
+}
+
+async fn next_event(events: &mut Events) -> Result<(), String> {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("no event from watcher")
+        .unwrap()
+}
+
+// Check that nothing else happens, like a failed reload of half-written files
+async fn no_more_events(events: &mut Events) {
+    let event = tokio::time::timeout(DEBOUNCE * 3, events.recv()).await;
+    assert!(event.is_err(), "unexpected event {event:?}");
+}
+
+// Get the certificate the listener currently gives clients, by connecting to it in memory
+async fn served_cert(tls_handle: &TlsHandle) -> Vec<u8> {
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let (client, _server) = futures_util::future::try_join(
+        tls_connector().connect(server_name(), client),
+        tls_handle.get().unwrap().accept(server),
+    )
+    .await
+    .unwrap();
+    client.get_ref().1.peer_certificates().unwrap()[0].0.clone()
+}
+
+// The first certificate in a PEM file, to compare with the one the listener gives
+fn cert_der(path: &str) -> Vec<u8> {
+    let pem = std::fs::read(path).unwrap();
+    rustls_pemfile::certs(&mut pem.as_slice())
+        .unwrap()
+        .remove(0)
+}
+
+#[tokio::test]
+async fn replaced_by_rename() {
+    let dir = TempDir::new("rename");
+    std::fs::copy(OLD_CERT, dir.join("cert.pem")).unwrap();
+    std::fs::copy(OLD_KEY, dir.join("key.pem")).unwrap();
+    let (tls_handle, mut events) = start(&dir.join("cert.pem"), &dir.join("key.pem")).await;
+    assert_eq!(served_cert(&tls_handle).await, cert_der(OLD_CERT));
+
+    // Like certbot and editors do, write new files and rename them over the old ones
+    std::fs::copy(NEW_CERT, dir.join("cert.pem.tmp")).unwrap();
+    std::fs::copy(NEW_KEY, dir.join("key.pem.tmp")).unwrap();
+    std::fs::rename(dir.join("cert.pem.tmp"), dir.join("cert.pem")).unwrap();
+    std::fs::rename(dir.join("key.pem.tmp"), dir.join("key.pem")).unwrap();
+
+    next_event(&mut events).await.unwrap();
+    assert_eq!(served_cert(&tls_handle).await, cert_der(NEW_CERT));
+    no_more_events(&mut events).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn swapped_through_symlink() {
+    use std::os::unix::fs::symlink;
+
+    // Laid out like a Kubernetes secret mount, where every file links through `..data` to the current version
+    let dir = TempDir::new("symlink");
+    std::fs::create_dir(dir.join("..v1")).unwrap();
+    std::fs::copy(OLD_CERT, dir.join("..v1/cert.pem")).unwrap();
+    std::fs::copy(OLD_KEY, dir.join("..v1/key.pem")).unwrap();
+    symlink("..v1", dir.join("..data")).unwrap();
+    symlink("..data/cert.pem", dir.join("cert.pem")).unwrap();
+    symlink("..data/key.pem", dir.join("key.pem")).unwrap();
+    let (tls_handle, mut events) = start(&dir.join("cert.pem"), &dir.join("key.pem")).await;
+
+    // Both files change at once when the `..data` link is swapped to the new version
+    std::fs::create_dir(dir.join("..v2")).unwrap();
+    std::fs::copy(NEW_CERT, dir.join("..v2/cert.pem")).unwrap();
+    std::fs::copy(NEW_KEY, dir.join("..v2/key.pem")).unwrap();
+    symlink("..v2", dir.join("..data_tmp")).unwrap();
+    std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+    std::fs::remove_dir_all(dir.join("..v1")).unwrap();
+
+    next_event(&mut events).await.unwrap();
+    assert_eq!(served_cert(&tls_handle).await, cert_der(NEW_CERT));
+    no_more_events(&mut events).await;
+}
+
+#[tokio::test]
+async fn written_one_after_the_other() {
+    let dir = TempDir::new("written");
+    std::fs::copy(OLD_CERT, dir.join("cert.pem")).unwrap();
+    std::fs::copy(OLD_KEY, dir.join("key.pem")).unwrap();
+    let (tls_handle, mut events) = start(&dir.join("cert.pem"), &dir.join("key.pem")).await;
+
+    // The certificate doesn't match the old key, so it must not be loaded until the key is written too
+    std::fs::copy(NEW_CERT, dir.join("cert.pem")).unwrap();
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    std::fs::copy(NEW_KEY, dir.join("key.pem")).unwrap();
+
+    next_event(&mut events).await.unwrap();
+    assert_eq!(served_cert(&tls_handle).await, cert_der(NEW_CERT));
+    no_more_events(&mut events).await;
+}
+
+#[tokio::test]
+async fn bad_files_keep_old_certificate() {
+    let dir = TempDir::new("bad");
+    std::fs::copy(OLD_CERT, dir.join("cert.pem")).unwrap();
+    std::fs::copy(OLD_KEY, dir.join("key.pem")).unwrap();
+    let (tls_handle, mut events) = start(&dir.join("cert.pem"), &dir.join("key.pem")).await;
+
+    // A certificate with a key that doesn't match it only fails the test handshake
+    std::fs::copy(NEW_CERT, dir.join("cert.pem")).unwrap();
+    let err = next_event(&mut events).await.unwrap_err();
+    assert!(err.starts_with("Check("), "{err}");
+    assert_eq!(served_cert(&tls_handle).await, cert_der(OLD_CERT));
+
+    std::fs::write(dir.join("key.pem"), "not a key").unwrap();
+    let err = next_event(&mut events).await.unwrap_err();
+    assert!(err.starts_with("Load("), "{err}");
+    assert_eq!(served_cert(&tls_handle).await, cert_der(OLD_CERT));
+
+    // Fixing the files loads them
+    std::fs::copy(NEW_KEY, dir.join("key.pem")).unwrap();
+    next_event(&mut events).await.unwrap();
+    assert_eq!(served_cert(&tls_handle).await, cert_der(NEW_CERT));
+}